@@ -0,0 +1,264 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use anyhow::anyhow;
+use colored::Colorize;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    config::read_config,
+    utils::{
+        authenticate, check_chroot, check_executables_root, log_rejection, set_error_pages,
+        templated_error,
+    },
+    BarnState,
+};
+
+// members of this group may use the admin endpoints
+pub const ADMIN_GROUP: &str = "admin";
+
+// keeps reloads from overlapping, whether they come from the endpoint or
+// SIGHUP. the lock holds the error (if any) of the last reload, so requests
+// that get coalesced into it can report its outcome
+#[derive(Default)]
+pub struct Reloads {
+    started: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+pub enum Reload {
+    Reloaded,
+    // a reload that started after this one was asked for has just finished
+    Coalesced,
+}
+
+// re-reads the config barn was started with and swaps it in. the listener
+// and the concurrency/viewer limits are only set up at startup, so changes
+// to those still need a restart
+pub async fn reload_config(state: web::Data<BarnState>) -> anyhow::Result<Reload> {
+    let ticket = state.reloads.started.load(Ordering::SeqCst);
+    let mut last_error = state.reloads.last_error.lock().await;
+    if state.reloads.started.load(Ordering::SeqCst) > ticket {
+        return match &*last_error {
+            Some(e) => Err(anyhow!("{}", e)),
+            None => Ok(Reload::Coalesced),
+        };
+    }
+    state.reloads.started.fetch_add(1, Ordering::SeqCst);
+
+    // reading the config and checking the root hit the disk, which would
+    // hold up every other request on this worker
+    let blocking_state = state.clone();
+    let result = web::block(move || {
+        let (config, _) = read_config(blocking_state.config_arg.clone())?;
+        check_executables_root(&config.options.root)?;
+        check_chroot(&config.options)?;
+        set_error_pages(&config.options.error_pages);
+        *blocking_state.config.write().unwrap() = Arc::new(config);
+        Ok(Reload::Reloaded)
+    })
+    .await
+    .unwrap_or_else(|e| Err(anyhow!("{}", e)));
+    *last_error = result.as_ref().err().map(|e| format!("{:#}", e));
+    result
+}
+
+// SIGHUP reloads the config the same way the endpoint does
+#[cfg(unix)]
+pub async fn reload_on_sighup(state: web::Data<BarnState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            println!(
+                "{} unable to listen for SIGHUP, reloads are only possible through the endpoint: {}",
+                "[warn]".bold().yellow(),
+                e
+            );
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        match reload_config(state.clone()).await {
+            Ok(_) => println!("{} config reloaded on SIGHUP", "[admin]".bold().blue()),
+            Err(e) => println!(
+                "{} config reload on SIGHUP failed, keeping the current config: {:#}",
+                "[admin]".bold().red(),
+                e
+            ),
+        }
+    }
+}
+
+pub async fn reload_handler(
+    req: HttpRequest,
+    creds: Option<BasicAuth>,
+    data: web::Data<BarnState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let config = data.config();
+    if !config.options.admin_endpoints {
+        return Err(templated_error("Not found", StatusCode::NOT_FOUND));
+    }
+
+    let user = authenticate(&req, creds, &config)?;
+    if !user.groups.iter().any(|group| group == ADMIN_GROUP) {
+        log_rejection(
+            &req,
+            &format!("user '{}' denied access to config reload", user.username),
+        );
+        return Err(templated_error(
+            "You don't have access to this endpoint",
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    match reload_config(data.clone()).await {
+        Ok(reload) => {
+            let coalesced = matches!(reload, Reload::Coalesced);
+            println!(
+                "{} config reloaded by '{}'{}",
+                "[admin]".bold().blue(),
+                user.username,
+                if coalesced {
+                    " (coalesced with a concurrent reload)"
+                } else {
+                    ""
+                }
+            );
+            Ok(HttpResponse::Ok().json(json!({ "reloaded": true, "coalesced": coalesced })))
+        }
+        Err(e) => {
+            println!(
+                "{} config reload by '{}' failed, keeping the current config: {:#}",
+                "[admin]".bold().red(),
+                user.username,
+                e
+            );
+            Ok(HttpResponse::UnprocessableEntity()
+                .json(json!({ "reloaded": false, "error": format!("{:#}", e) })))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, web};
+    use serde_json::Value;
+
+    use super::reload_config;
+    use crate::{
+        testing::{app_with_state, basic_auth, TestRoot, ERROR_PAGE_TESTS},
+        BarnState,
+    };
+
+    fn admin_config(root: &TestRoot, description: &str) -> String {
+        format!(
+            concat!(
+                "[options]\nroot = {:?}\nadmin_endpoints = true\n",
+                "[[user]]\nusername = \"admin\"\npassword = \"password\"\ngroups = [\"admin\"]\n",
+                "[[executable]]\nname = \"hello.sh\"\ndescription = {:?}\n",
+            ),
+            root.path().display().to_string(),
+            description
+        )
+    }
+
+    #[actix_web::test]
+    async fn edited_configs_are_reloaded() {
+        let _error_pages = ERROR_PAGE_TESTS.lock().await;
+        let root = TestRoot::new();
+        let path = root.file("barn.toml", &admin_config(&root, "before"));
+        let config = root.config(&admin_config(&root, "before"));
+        let state = web::Data::new(BarnState::new(config, Some(path.display().to_string())));
+        let app = app_with_state(state.clone()).await;
+        let reload = || {
+            test::TestRequest::post()
+                .uri("/admin/reload")
+                .insert_header(basic_auth("admin", "password"))
+                .to_request()
+        };
+
+        root.file("barn.toml", &admin_config(&root, "after"));
+        let response = test::call_service(&app, reload()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["reloaded"], true);
+        let description = |state: &BarnState| {
+            state
+                .config()
+                .executable("hello.sh")
+                .unwrap()
+                .description
+                .clone()
+        };
+        assert_eq!(description(&state).as_deref(), Some("after"));
+
+        // a broken config is reported and the current one kept
+        root.file("barn.toml", "[options\n");
+        let response = test::call_service(&app, reload()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["reloaded"], false);
+        assert!(body["error"].as_str().is_some_and(|e| !e.is_empty()));
+        assert_eq!(description(&state).as_deref(), Some("after"));
+    }
+
+    #[actix_web::test]
+    async fn concurrent_reloads_leave_one_config() {
+        let _error_pages = ERROR_PAGE_TESTS.lock().await;
+        let root = TestRoot::new();
+        let path = root.file("barn.toml", &admin_config(&root, "before"));
+        let state = web::Data::new(BarnState::new(
+            root.config(&admin_config(&root, "before")),
+            Some(path.display().to_string()),
+        ));
+
+        root.file("barn.toml", &admin_config(&root, "after"));
+        let (first, second) =
+            futures::join!(reload_config(state.clone()), reload_config(state.clone()));
+        assert!(first.is_ok() && second.is_ok());
+        let config = state.config();
+        assert_eq!(
+            config
+                .executable("hello.sh")
+                .unwrap()
+                .description
+                .as_deref(),
+            Some("after")
+        );
+        assert_eq!(config.options.root, root.path());
+        assert!(state.reloads.last_error.lock().await.is_none());
+    }
+
+    #[actix_web::test]
+    async fn deleted_configs_keep_the_last_good_one() {
+        let root = TestRoot::new();
+        let path = root.file("barn.toml", &admin_config(&root, "last good"));
+        let config = root.config(&admin_config(&root, "last good"));
+        let state = web::Data::new(BarnState::new(config, Some(path.display().to_string())));
+
+        std::fs::remove_file(&path).unwrap();
+        let Err(error) = reload_config(state.clone()).await else {
+            panic!("a missing config was reloaded");
+        };
+        assert!(
+            error.to_string().contains("Unable to read the config file"),
+            "{}",
+            error
+        );
+        assert_eq!(
+            state
+                .config()
+                .executable("hello.sh")
+                .and_then(|executable| executable.description.as_deref()),
+            Some("last good")
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use async_stream::stream;
+use bytes::Bytes;
+use futures::{pin_mut, Stream, StreamExt};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::Notify, time::sleep};
+
+// executions whose output is shared between every client viewing them
+#[derive(Default)]
+pub struct Broadcasts {
+    runs: Mutex<HashMap<String, Arc<SharedRun>>>,
+}
+
+#[derive(Default)]
+struct SharedRun {
+    state: Mutex<RunState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct RunState {
+    chunks: Vec<Bytes>,
+    started: bool,
+    abandoned: bool,
+    finished: bool,
+}
+
+pub enum Joined<S> {
+    // the output of a run that was already started under the key
+    Shared(S),
+    // nothing runs under the key yet. it is now held for the caller, so
+    // requests arriving meanwhile wait for its run instead of starting one
+    Reserved(Reservation),
+}
+
+// a key held for a run that has yet to start. dropping it without starting
+// the run lets the requests waiting on it try again on their own
+pub struct Reservation {
+    broadcasts: Arc<Broadcasts>,
+    key: String,
+    run: Option<Arc<SharedRun>>,
+}
+
+impl Broadcasts {
+    // attach to the execution running under this key, or reserve the key if
+    // there is none. a run that was reserved but not yet started is waited for
+    pub async fn join(
+        self: &Arc<Self>,
+        key: &str,
+    ) -> Joined<impl Stream<Item = io::Result<Bytes>>> {
+        loop {
+            let run = {
+                let mut runs = self.runs.lock().unwrap();
+                match runs.get(key) {
+                    Some(run) => run.clone(),
+                    None => {
+                        let run = Arc::new(SharedRun::default());
+                        runs.insert(key.to_string(), run.clone());
+                        return Joined::Reserved(Reservation {
+                            broadcasts: self.clone(),
+                            key: key.to_string(),
+                            run: Some(run),
+                        });
+                    }
+                }
+            };
+
+            loop {
+                let (started, abandoned, notified) = {
+                    let state = run.state.lock().unwrap();
+                    (state.started, state.abandoned, run.notify.notified())
+                };
+                if started {
+                    return Joined::Shared(subscribe(run.clone()));
+                }
+                if abandoned {
+                    break;
+                }
+                notified.await;
+            }
+        }
+    }
+
+    // forget the run under the key, unless it has been replaced since
+    fn release(&self, key: &str, run: &Arc<SharedRun>) {
+        let mut runs = self.runs.lock().unwrap();
+        if runs.get(key).is_some_and(|entry| Arc::ptr_eq(entry, run)) {
+            runs.remove(key);
+        }
+    }
+}
+
+impl Reservation {
+    // drive `output` in the background and hand back a subscription to it,
+    // so that it keeps running even if the client that started it leaves.
+    // a finished run can still be joined for `linger`
+    pub fn start<S>(
+        mut self,
+        output: S,
+        linger: Option<Duration>,
+    ) -> impl Stream<Item = io::Result<Bytes>>
+    where
+        S: Stream<Item = io::Result<Bytes>> + 'static,
+    {
+        let run = self.run.take().unwrap();
+        let broadcasts = self.broadcasts.clone();
+        let key = std::mem::take(&mut self.key);
+        run.state.lock().unwrap().started = true;
+        run.notify.notify_waiters();
+
+        let producer = run.clone();
+        actix_web::rt::spawn(async move {
+            pin_mut!(output);
+            while let Some(Ok(chunk)) = output.next().await {
+                producer.state.lock().unwrap().chunks.push(chunk);
+                producer.notify.notify_waiters();
+            }
+
+            producer.state.lock().unwrap().finished = true;
+            producer.notify.notify_waiters();
+
+            if let Some(linger) = linger {
+                sleep(linger).await;
+            }
+
+            broadcasts.release(&key, &producer);
+        });
+
+        subscribe(run)
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(run) = self.run.take() {
+            self.broadcasts.release(&self.key, &run);
+            run.state.lock().unwrap().abandoned = true;
+            run.notify.notify_waiters();
+        }
+    }
+}
+
+// replays everything produced so far, then follows the run until it ends
+fn subscribe(run: Arc<SharedRun>) -> impl Stream<Item = io::Result<Bytes>> {
+    stream! {
+        let mut next = 0;
+
+        loop {
+            // the notification is registered while the lock is held, so
+            // nothing pushed after this snapshot can be missed
+            let (chunks, finished, notified) = {
+                let state = run.state.lock().unwrap();
+                let chunks = state.chunks[next..].to_vec();
+                next = state.chunks.len();
+                (chunks, state.finished, run.notify.notified())
+            };
+
+            for chunk in chunks {
+                yield Ok(chunk);
+            }
+
+            if finished {
+                break;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    async fn text(output: impl Stream<Item = io::Result<Bytes>>) -> String {
+        let chunks = output.map(|chunk| chunk.unwrap().to_vec()).concat().await;
+        String::from_utf8(chunks).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn waiters_follow_the_reserved_run() {
+        let broadcasts = Arc::new(Broadcasts::default());
+        let Joined::Reserved(reservation) = broadcasts.join("key").await else {
+            panic!("nothing runs under the key yet");
+        };
+
+        let waiter = actix_web::rt::spawn({
+            let broadcasts = broadcasts.clone();
+            async move {
+                match broadcasts.join("key").await {
+                    Joined::Shared(shared) => text(shared).await,
+                    Joined::Reserved(_) => panic!("the key was reserved"),
+                }
+            }
+        });
+        sleep(Duration::from_millis(50)).await;
+
+        let output = stream::iter([Ok(Bytes::from("one\n")), Ok(Bytes::from("two\n"))]);
+        let started = reservation.start(output, None);
+        assert_eq!(text(started).await, "one\ntwo\n");
+        assert_eq!(waiter.await.unwrap(), "one\ntwo\n");
+    }
+
+    #[actix_web::test]
+    async fn dropped_reservations_let_waiters_try_again() {
+        let broadcasts = Arc::new(Broadcasts::default());
+        let Joined::Reserved(reservation) = broadcasts.join("key").await else {
+            panic!("nothing runs under the key yet");
+        };
+
+        let waiter = actix_web::rt::spawn({
+            let broadcasts = broadcasts.clone();
+            async move { matches!(broadcasts.join("key").await, Joined::Reserved(_)) }
+        });
+        sleep(Duration::from_millis(50)).await;
+
+        drop(reservation);
+        assert!(waiter.await.unwrap());
+    }
+}
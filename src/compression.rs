@@ -0,0 +1,48 @@
+use std::io::{self, Write};
+
+use actix_web::{http::header, HttpRequest};
+use async_stream::try_stream;
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use futures::{pin_mut, Stream, StreamExt};
+
+// whether the client will take a gzip encoded response
+pub fn accepts_gzip(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| param.replace(' ', "") == "q=0");
+            name.eq_ignore_ascii_case("gzip") && !refused
+        })
+}
+
+pub fn gzip_bytes(body: &[u8], level: u32) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+// gzips the output as it streams. every chunk is flushed through, so the
+// viewer still sees lines as the executable prints them
+pub fn gzip<S, E>(stream: S, level: u32) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: From<io::Error>,
+{
+    try_stream! {
+        pin_mut!(stream);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+
+        while let Some(chunk) = stream.next().await {
+            encoder.write_all(&chunk?)?;
+            encoder.flush()?;
+            yield Bytes::from(std::mem::take(encoder.get_mut()));
+        }
+
+        yield Bytes::from(encoder.finish()?);
+    }
+}
@@ -1,11 +1,25 @@
 use anyhow::Context;
+use chrono::{
+    format::{Item, StrftimeItems},
+    Datelike, FixedOffset, NaiveDateTime, NaiveTime, Weekday,
+};
 use colored::{ColoredString, Colorize};
+use ipnet::IpNet;
 use regex::Regex;
 use serde::{de, Deserialize, Deserializer};
+
+use crate::{
+    admin::ADMIN_GROUP,
+    output::Renderer,
+    signals::signal_number,
+    utils::{list_executables, untrusted_executable},
+};
 use std::fs;
 use std::{
+    collections::HashMap,
     fs::{read_dir, DirEntry},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 // structs
@@ -17,6 +31,12 @@ pub struct Config {
     pub user: Vec<User>,
     #[serde(default = "default_vec")]
     pub group: Vec<Group>,
+    #[serde(default = "default_vec")]
+    pub executable: Vec<Executable>,
+    #[serde(default = "default_vec")]
+    pub route: Vec<Route>,
+    #[serde(default = "default_vec")]
+    pub rewrite: Vec<Rewrite>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -27,6 +47,288 @@ pub struct Options {
     pub host: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    #[serde(default = "default_vec")]
+    pub trusted_proxies: Vec<IpNet>,
+    #[serde(default)]
+    pub timestamp_lines: bool,
+    #[serde(default)]
+    pub timestamp_format: Option<String>,
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    #[serde(default)]
+    pub max_duration: Option<u64>,
+    // overrides of max_duration keyed by http method, like { POST = 600 }
+    #[serde(default)]
+    pub max_duration_by_method: HashMap<String, u64>,
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    #[serde(default)]
+    pub trusted_user_header: Option<String>,
+    #[serde(default)]
+    pub output_prefix: Option<OutputText>,
+    #[serde(default)]
+    pub output_suffix: Option<OutputText>,
+    #[serde(default)]
+    pub auth: AuthMode,
+    #[serde(default)]
+    pub mime_types: HashMap<String, String>,
+    #[serde(default)]
+    pub max_viewers: Option<usize>,
+    #[serde(default)]
+    pub broadcast: bool,
+    #[serde(default)]
+    pub post_exec: Option<String>,
+    #[serde(default = "default_post_exec_timeout")]
+    pub post_exec_timeout: u64,
+    #[serde(default)]
+    pub webhook: Option<String>,
+    #[serde(default = "default_webhook_retries")]
+    pub webhook_retries: u32,
+    #[serde(default = "default_webhook_tail_lines")]
+    pub webhook_tail_lines: usize,
+    #[serde(default)]
+    pub collapse_blank_lines: BlankLines,
+    #[serde(default)]
+    pub backpressure: Backpressure,
+    #[serde(default)]
+    pub maintenance: bool,
+    #[serde(default)]
+    pub progress_marker: Option<String>,
+    #[serde(default = "default_true")]
+    pub show_stderr: bool,
+    #[serde(default)]
+    pub kill_grace: Option<u64>,
+    #[serde(default)]
+    pub require_header: Option<String>,
+    #[serde(default)]
+    pub tail_lines: Option<usize>,
+    #[serde(default)]
+    pub tail_bytes: Option<usize>,
+    #[serde(default)]
+    pub log_commands: bool,
+    #[serde(default)]
+    pub require_https: bool,
+    #[serde(default)]
+    pub case_insensitive_paths: bool,
+    #[serde(default)]
+    pub admin_endpoints: bool,
+    #[serde(default = "default_vec")]
+    pub default_groups: Vec<String>,
+    #[serde(default)]
+    pub abort_on_marker: Option<String>,
+    #[serde(default = "default_vec")]
+    pub listen: Vec<Listen>,
+    #[serde(default = "default_true")]
+    pub normalize_newlines: bool,
+    #[serde(default)]
+    pub audit: bool,
+    #[serde(default)]
+    pub audit_include_query: bool,
+    #[serde(default = "default_vec")]
+    pub audit_headers: Vec<String>,
+    // bytes, like min_free_disk
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub memory_guard: Option<u64>,
+    #[serde(default)]
+    pub suggest_executables: bool,
+    #[serde(default)]
+    pub delay_template: bool,
+    #[serde(default)]
+    pub show_authorizing_groups: bool,
+    #[serde(default)]
+    pub dedup_window: Option<u64>,
+    #[serde(default)]
+    pub heartbeat_interval: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_utc_offset")]
+    pub schedule_utc_offset: Option<FixedOffset>,
+    #[serde(default, deserialize_with = "deserialize_regexes")]
+    pub redact: Vec<Regex>,
+    #[serde(default)]
+    pub throttle_page: Option<OutputText>,
+    #[serde(default)]
+    pub authz_hook: Option<String>,
+    #[serde(default = "default_authz_hook_timeout")]
+    pub authz_hook_timeout: u64,
+    #[serde(default)]
+    pub max_output_bytes: Option<u64>,
+    #[serde(default = "default_vec")]
+    pub links: Vec<Link>,
+    #[serde(default)]
+    pub chroot: Option<PathBuf>,
+    #[serde(default)]
+    pub expand_tabs: Option<usize>,
+    #[serde(default)]
+    pub compression: bool,
+    #[serde(
+        default = "default_compression_level",
+        deserialize_with = "deserialize_compression_level"
+    )]
+    pub compression_level: u32,
+    #[serde(default = "default_vec")]
+    pub warmup: Vec<String>,
+    #[serde(default)]
+    pub output_log_dir: Option<PathBuf>,
+    #[serde(default = "default_output_log_keep")]
+    pub output_log_keep: usize,
+    #[serde(default)]
+    pub output_log_max_bytes: Option<u64>,
+    // logs older than this many seconds are removed
+    #[serde(default)]
+    pub output_log_max_age: Option<u64>,
+    // the oldest logs are removed once all of them together take up more
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub output_log_max_total_bytes: Option<u64>,
+    #[serde(default)]
+    pub password_policy: Option<PasswordPolicy>,
+    #[serde(default)]
+    pub buffer_until_bytes: Option<u64>,
+    #[serde(default = "default_vec")]
+    pub footer_info: Vec<FooterInfo>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub min_free_disk: Option<u64>,
+    #[serde(default)]
+    pub max_lines: Option<u64>,
+    #[serde(default)]
+    pub kill_on_max_lines: bool,
+    #[serde(default = "default_true")]
+    pub raw_nosniff: bool,
+    #[serde(default)]
+    pub request_id_env: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_executable_size: Option<u64>,
+    #[serde(default)]
+    pub strict_executable_size: bool,
+    #[serde(default)]
+    pub fallback_executable: Option<String>,
+    #[serde(default)]
+    pub spawn_rate: Option<f64>,
+    #[serde(default = "default_spawn_rate_max_wait")]
+    pub spawn_rate_max_wait: u64,
+    // tell clients how much of options.spawn_rate they have left
+    #[serde(default)]
+    pub rate_limit_headers: bool,
+    #[serde(default)]
+    pub show_env: bool,
+    // seconds a request may wait for a max_concurrent slot, queued fairly
+    // among users, before it's turned away
+    #[serde(default)]
+    pub fair_queue_timeout: Option<u64>,
+    #[serde(default)]
+    pub collapse_carriage_returns: bool,
+    // uid every executable has to be owned by
+    #[serde(default)]
+    pub require_owner: Option<u32>,
+    #[serde(default)]
+    pub reject_writable: bool,
+    #[serde(default)]
+    pub result_trailer: bool,
+    #[serde(default)]
+    pub strict_group_regexes: bool,
+    #[serde(default)]
+    pub negotiate_renderer: bool,
+    #[serde(default)]
+    pub flush_coalesce_ms: Option<u64>,
+    // pages used instead of the generic one for errors with these status
+    // codes, with {message} standing in for the error message
+    #[serde(default, deserialize_with = "deserialize_error_pages")]
+    pub error_pages: HashMap<u16, OutputText>,
+    #[serde(default)]
+    pub path_scopes: bool,
+    // prints the names of the executables that may be run, one per line,
+    // for when they aren't simply the files in the root
+    #[serde(default)]
+    pub discovery_command: Option<String>,
+    #[serde(default = "default_discovery_ttl")]
+    pub discovery_ttl: u64,
+    // without it, executables are run without arguments from the query
+    #[serde(default)]
+    pub query_args: Option<QueryArgs>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Listen {
+    #[serde(default = "default_host")]
+    pub host: String,
+    pub port: u16,
+}
+
+// details about barn itself shown at the bottom of the viewer
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FooterInfo {
+    Version,
+    Hostname,
+    Executable,
+}
+
+// what configured passwords are checked against when the config is read.
+// weak ones are warned about, or refused outright when strict
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PasswordPolicy {
+    #[serde(default)]
+    pub min_length: usize,
+    // at least three of lowercase, uppercase, digits and symbols
+    #[serde(default)]
+    pub complexity: bool,
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl PasswordPolicy {
+    // why the password falls short of the policy, if it does
+    fn weakness(&self, password: &str) -> Option<String> {
+        if password.chars().count() < self.min_length {
+            return Some(format!("is shorter than {} characters", self.min_length));
+        }
+
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ];
+        if self.complexity && classes.iter().filter(|&&class| class).count() < 3 {
+            return Some(
+                "needs at least three of lowercase, uppercase, digits and symbols".to_string(),
+            );
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backpressure {
+    #[default]
+    Block,
+    Drop,
+    Kill,
+}
+
+// related endpoints advertised in Link headers on execution responses
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Link {
+    Index,
+    Raw,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BlankLines {
+    #[default]
+    Off,
+    Collapse,
+    Strip,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    #[default]
+    Basic,
+    None,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -36,13 +338,211 @@ pub struct User {
     pub groups: Vec<String>,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct Executable {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    // how the executable expects to be invoked, e.g. "?arg=--full"
+    #[serde(default)]
+    pub args_help: Option<String>,
+    #[serde(default)]
+    pub render: Option<Renderer>,
+    #[serde(default)]
+    pub lock: Option<String>,
+    #[serde(default)]
+    pub stdin: Option<StdinMode>,
+    // media types a request body may have, any if unset
+    #[serde(default)]
+    pub allowed_content_types: Option<Vec<String>>,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+    #[serde(default, deserialize_with = "deserialize_regexes")]
+    pub line_include: Vec<Regex>,
+    #[serde(default, deserialize_with = "deserialize_regexes")]
+    pub line_exclude: Vec<Regex>,
+    #[serde(default = "default_vec")]
+    pub form: Vec<FormField>,
+    // hosts that may run the executable without credentials, on top of
+    // the ones trusted by its groups
+    #[serde(default = "default_vec")]
+    pub trusted_ips: Vec<IpNet>,
+}
+
+// a parameter asked for by the form barn shows for an executable
+#[derive(Debug, Deserialize, Clone)]
+pub struct FormField {
+    pub name: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(rename = "type", default)]
+    pub field_type: FieldType,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    #[default]
+    Text,
+    Number,
+    Checkbox,
+}
+
+// what an executable expects on stdin. without a mode, it inherits barn's
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StdinMode {
+    // the executable waits for input, so it can't be run without any
+    Required,
+    // stdin is closed straight away
+    Forbidden,
+}
+
+// how query parameters are handed to the executable as arguments
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryArgs {
+    // the values, in the order they appear in the query
+    Positional,
+    // --name value for each parameter
+    Flags,
+}
+
+// a public url path served by an executable with a different name
+#[derive(Debug, Deserialize, Clone)]
+pub struct Route {
+    pub path: String,
+    pub executable: String,
+}
+
+// sends some users of an executable to another one once they have been
+// authorized, e.g. a beta group to a new version of a script
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rewrite {
+    pub executable: String,
+    pub to: String,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Group {
     pub name: String,
     pub regex: Regex,
+    pub output_prefix: Option<OutputText>,
+    pub output_suffix: Option<OutputText>,
+    pub post_exec: Option<String>,
+    pub allowed_params: Option<Vec<String>>,
+    pub param_regex: HashMap<String, Regex>,
+    pub show_stderr: Option<bool>,
+    pub require_header: Option<String>,
+    pub max_concurrent: Option<usize>,
+    pub render: Option<Renderer>,
+    pub trusted_ips: Vec<IpNet>,
+    pub schedule: Vec<Schedule>,
+    pub hidden: bool,
+    pub env_file: Option<PathBuf>,
+    pub line_include: Vec<Regex>,
+    pub line_exclude: Vec<Regex>,
+    // signals the user running one of the group's executables may send it
+    pub signals: Vec<i32>,
+    pub allowed_content_types: Option<Vec<String>>,
+}
+
+// a window during which a group's executables may run, like
+// "Mon-Fri 09:00-17:00" or "22:00-06:00"
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    text: String,
+    days: Option<(Weekday, Weekday)>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum OutputText {
+    Text(String),
+    File { file: PathBuf },
 }
 
 // impls
+impl Options {
+    // where barn should listen, options.listen if set, host and port otherwise
+    pub fn bind_targets(&self) -> Vec<(String, u16)> {
+        if self.listen.is_empty() {
+            return vec![(self.host.clone(), self.port)];
+        }
+
+        self.listen
+            .iter()
+            .map(|listen| (listen.host.clone(), listen.port))
+            .collect()
+    }
+}
+
+impl Config {
+    pub fn executable(&self, name: &str) -> Option<&Executable> {
+        self.executable.iter().find(|entry| entry.name == name)
+    }
+
+    // hidden executables can still be run, they just aren't advertised
+    pub fn is_hidden(&self, name: &str) -> bool {
+        self.executable(name)
+            .is_some_and(|executable| executable.hidden)
+            || self
+                .group
+                .iter()
+                .any(|entry| entry.hidden && entry.regex.is_match(name))
+    }
+
+    // the users whose passwords fall short of options.password_policy
+    fn weak_passwords(&self) -> Vec<String> {
+        let Some(policy) = &self.options.password_policy else {
+            return Vec::new();
+        };
+
+        self.user
+            .iter()
+            .filter_map(|user| {
+                let weakness = policy.weakness(&user.password)?;
+                Some(format!("the password of '{}' {}", user.username, weakness))
+            })
+            .collect()
+    }
+
+    // the executable a [[rewrite]] sends the user to instead, if any. a
+    // rewrite without a group or user applies to everyone
+    pub fn rewrite(&self, user: &User, executable: &str) -> Option<&str> {
+        self.rewrite
+            .iter()
+            .filter(|entry| entry.executable == executable)
+            .find(|entry| {
+                entry
+                    .group
+                    .as_ref()
+                    .is_none_or(|group| user.groups.contains(group))
+                    && entry
+                        .user
+                        .as_ref()
+                        .is_none_or(|username| *username == user.username)
+            })
+            .map(|entry| entry.to.as_str())
+    }
+
+    // the executable a [[route]] maps the request path to, if any
+    pub fn route(&self, path: &str) -> Option<&str> {
+        self.route
+            .iter()
+            .find(|entry| entry.path.trim_start_matches('/') == path)
+            .map(|entry| entry.executable.as_str())
+    }
+}
+
 impl<'a> Deserialize<'a> for Group {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -52,19 +552,169 @@ impl<'a> Deserialize<'a> for Group {
         struct GroupHelper {
             name: String,
             regex: String,
+            #[serde(default)]
+            output_prefix: Option<OutputText>,
+            #[serde(default)]
+            output_suffix: Option<OutputText>,
+            #[serde(default)]
+            post_exec: Option<String>,
+            #[serde(default)]
+            allowed_params: Option<Vec<String>>,
+            #[serde(default)]
+            param_regex: HashMap<String, String>,
+            #[serde(default)]
+            show_stderr: Option<bool>,
+            #[serde(default)]
+            require_header: Option<String>,
+            #[serde(default)]
+            max_concurrent: Option<usize>,
+            #[serde(default)]
+            render: Option<Renderer>,
+            #[serde(default = "default_vec")]
+            trusted_ips: Vec<IpNet>,
+            #[serde(default = "default_vec")]
+            schedule: Vec<String>,
+            #[serde(default)]
+            hidden: bool,
+            #[serde(default)]
+            env_file: Option<PathBuf>,
+            #[serde(default, deserialize_with = "deserialize_regexes")]
+            line_include: Vec<Regex>,
+            #[serde(default, deserialize_with = "deserialize_regexes")]
+            line_exclude: Vec<Regex>,
+            #[serde(default)]
+            signals: Vec<String>,
+            #[serde(default)]
+            allowed_content_types: Option<Vec<String>>,
         }
 
         let helper = GroupHelper::deserialize(deserializer)?;
         let regex = Regex::new(&helper.regex)
             .map_err(|e| de::Error::custom(format!("malformed regex: {}", e)))?;
 
+        let schedule = helper
+            .schedule
+            .iter()
+            .map(|text| text.parse::<Schedule>().map_err(de::Error::custom))
+            .collect::<Result<_, _>>()?;
+
+        let signals = helper
+            .signals
+            .iter()
+            .map(|name| {
+                signal_number(name)
+                    .ok_or_else(|| de::Error::custom(format!("unknown signal '{}'", name)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        // a parameter's whole value has to match, not just part of it
+        let param_regex = helper
+            .param_regex
+            .into_iter()
+            .map(|(param, pattern)| {
+                Regex::new(&format!("^(?:{})$", pattern))
+                    .map(|regex| (param.clone(), regex))
+                    .map_err(|e| {
+                        de::Error::custom(format!("malformed regex for param '{}': {}", param, e))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+
         Ok(Group {
             name: helper.name,
             regex,
+            output_prefix: helper.output_prefix,
+            output_suffix: helper.output_suffix,
+            post_exec: helper.post_exec,
+            allowed_params: helper.allowed_params,
+            param_regex,
+            show_stderr: helper.show_stderr,
+            require_header: helper.require_header,
+            max_concurrent: helper.max_concurrent,
+            render: helper.render,
+            trusted_ips: helper.trusted_ips,
+            schedule,
+            hidden: helper.hidden,
+            env_file: helper.env_file,
+            line_include: helper.line_include,
+            line_exclude: helper.line_exclude,
+            signals,
+            allowed_content_types: helper.allowed_content_types,
         })
     }
 }
 
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let malformed = || format!("malformed schedule '{}'", text);
+        let parse_time =
+            |time: &str| NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| malformed());
+
+        let (days, times) = match text.trim().split_once(' ') {
+            Some((days, times)) => {
+                let (first, last) = days.split_once('-').unwrap_or((days, days));
+                let first = first.parse::<Weekday>().map_err(|_| malformed())?;
+                let last = last.parse::<Weekday>().map_err(|_| malformed())?;
+                (Some((first, last)), times.trim())
+            }
+            None => (None, text.trim()),
+        };
+
+        let (start, end) = times.split_once('-').ok_or_else(malformed)?;
+        Ok(Schedule {
+            text: text.to_string(),
+            days,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+impl Schedule {
+    // windows ending before they start run past midnight, and days
+    // ranges like Fri-Mon wrap around the week
+    pub fn allows(&self, now: NaiveDateTime) -> bool {
+        let in_range = |value: u32, first: u32, last: u32| {
+            if first <= last {
+                (first..=last).contains(&value)
+            } else {
+                value >= first || value <= last
+            }
+        };
+
+        let on_day = self.days.is_none_or(|(first, last)| {
+            in_range(
+                now.weekday().num_days_from_monday(),
+                first.num_days_from_monday(),
+                last.num_days_from_monday(),
+            )
+        });
+        let time = now.time();
+        let in_window = if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        };
+
+        on_day && in_window
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl OutputText {
+    pub fn read(&self) -> std::io::Result<String> {
+        match self {
+            OutputText::Text(text) => Ok(text.clone()),
+            OutputText::File { file } => fs::read_to_string(file),
+        }
+    }
+}
+
 // default values
 impl Default for Options {
     fn default() -> Self {
@@ -72,6 +722,96 @@ impl Default for Options {
             root: default_root(),
             host: default_host(),
             port: default_port(),
+            trusted_proxies: default_vec(),
+            timestamp_lines: false,
+            timestamp_format: None,
+            idle_timeout: None,
+            max_duration: None,
+            max_duration_by_method: HashMap::new(),
+            max_concurrent: None,
+            trusted_user_header: None,
+            output_prefix: None,
+            output_suffix: None,
+            auth: AuthMode::default(),
+            mime_types: HashMap::new(),
+            max_viewers: None,
+            broadcast: false,
+            post_exec: None,
+            post_exec_timeout: default_post_exec_timeout(),
+            webhook: None,
+            webhook_retries: default_webhook_retries(),
+            webhook_tail_lines: default_webhook_tail_lines(),
+            collapse_blank_lines: BlankLines::default(),
+            backpressure: Backpressure::default(),
+            maintenance: false,
+            progress_marker: None,
+            show_stderr: true,
+            kill_grace: None,
+            require_header: None,
+            tail_lines: None,
+            tail_bytes: None,
+            log_commands: false,
+            require_https: false,
+            case_insensitive_paths: false,
+            admin_endpoints: false,
+            default_groups: default_vec(),
+            abort_on_marker: None,
+            listen: default_vec(),
+            normalize_newlines: true,
+            audit: false,
+            audit_include_query: false,
+            audit_headers: default_vec(),
+            memory_guard: None,
+            suggest_executables: false,
+            delay_template: false,
+            show_authorizing_groups: false,
+            dedup_window: None,
+            heartbeat_interval: None,
+            schedule_utc_offset: None,
+            redact: default_vec(),
+            throttle_page: None,
+            authz_hook: None,
+            authz_hook_timeout: default_authz_hook_timeout(),
+            max_output_bytes: None,
+            links: default_vec(),
+            chroot: None,
+            expand_tabs: None,
+            compression: false,
+            compression_level: default_compression_level(),
+            warmup: default_vec(),
+            output_log_dir: None,
+            output_log_keep: default_output_log_keep(),
+            output_log_max_bytes: None,
+            output_log_max_age: None,
+            output_log_max_total_bytes: None,
+            password_policy: None,
+            buffer_until_bytes: None,
+            footer_info: default_vec(),
+            min_free_disk: None,
+            max_lines: None,
+            kill_on_max_lines: false,
+            raw_nosniff: true,
+            request_id_env: None,
+            max_executable_size: None,
+            strict_executable_size: false,
+            fallback_executable: None,
+            spawn_rate: None,
+            spawn_rate_max_wait: default_spawn_rate_max_wait(),
+            rate_limit_headers: false,
+            show_env: false,
+            fair_queue_timeout: None,
+            collapse_carriage_returns: false,
+            require_owner: None,
+            reject_writable: false,
+            result_trailer: false,
+            strict_group_regexes: false,
+            negotiate_renderer: false,
+            flush_coalesce_ms: None,
+            error_pages: HashMap::new(),
+            path_scopes: false,
+            discovery_command: None,
+            discovery_ttl: default_discovery_ttl(),
+            query_args: None,
         }
     }
 }
@@ -92,43 +832,429 @@ fn default_port() -> u16 {
     8080
 }
 
+fn default_post_exec_timeout() -> u64 {
+    30
+}
+
+fn default_spawn_rate_max_wait() -> u64 {
+    5
+}
+
+fn default_discovery_ttl() -> u64 {
+    60
+}
+
+fn default_output_log_keep() -> usize {
+    100
+}
+
+fn default_compression_level() -> u32 {
+    6
+}
+
+fn default_authz_hook_timeout() -> u64 {
+    10
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+fn default_webhook_tail_lines() -> usize {
+    20
+}
+
+fn deserialize_utc_offset<'a, D>(deserializer: D) -> Result<Option<FixedOffset>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|offset| {
+            offset
+                .parse()
+                .map_err(|_| de::Error::custom(format!("malformed UTC offset '{}'", offset)))
+        })
+        .transpose()
+}
+
+// gzip levels go from 0 (store only) to 9 (smallest output)
+fn deserialize_compression_level<'a, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    let level = u32::deserialize(deserializer)?;
+    if level > 9 {
+        return Err(de::Error::custom(format!(
+            "compression level {} is out of range, gzip takes 0 to 9",
+            level
+        )));
+    }
+
+    Ok(level)
+}
+
+// error pages keyed by status code. toml keys are always strings, so the
+// codes are parsed here
+fn deserialize_error_pages<'a, D>(deserializer: D) -> Result<HashMap<u16, OutputText>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    HashMap::<String, OutputText>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(status, page)| {
+            status
+                .parse::<u16>()
+                .ok()
+                .filter(|status| (400..600).contains(status))
+                .map(|status| (status, page))
+                .ok_or_else(|| {
+                    de::Error::custom(format!("'{}' isn't an error status code", status))
+                })
+        })
+        .collect()
+}
+
+// a number of bytes, either as is or human-friendly like "512M" or "2GiB"
+fn deserialize_size<'a, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+
+    let text = match Option::<Size>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Size::Bytes(bytes)) => return Ok(Some(bytes)),
+        Some(Size::Text(text)) => text,
+    };
+
+    let malformed = || de::Error::custom(format!("malformed size '{}'", text));
+    let trimmed = text.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number = number.parse::<f64>().map_err(|_| malformed())?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(malformed()),
+    };
+
+    // the cast saturates, so absurdly large sizes end up as u64::MAX
+    Ok(Some((number * multiplier as f64) as u64))
+}
+
+fn deserialize_regexes<'a, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| de::Error::custom(format!("malformed regex: {}", e)))
+        })
+        .collect()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// replaces every ${VAR} in the config with the value of that environment
+// variable, so a single config can be carried between environments. values
+// are written so they can't change the structure of the config around them
+fn interpolate_env(config_str: &str) -> anyhow::Result<String> {
+    let variable = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    let mut interpolated = String::with_capacity(config_str.len());
+
+    for (index, line) in config_str.split_inclusive('\n').enumerate() {
+        // commented out lines shouldn't need their variables set
+        if line.trim_start().starts_with('#') {
+            interpolated.push_str(line);
+            continue;
+        }
+
+        let key = || {
+            line.split(['=', ':'])
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .trim_matches('"')
+                .to_string()
+        };
+        let mut last = 0;
+        for captures in variable.captures_iter(line) {
+            let whole = captures.get(0).unwrap();
+            let name = &captures[1];
+            let value = std::env::var(name).map_err(|_| {
+                anyhow::anyhow!(
+                    "Environment variable '{}' used by '{}' (line {}) is not set",
+                    name,
+                    key(),
+                    index + 1
+                )
+            })?;
+            let value = quote_env_value(&value, &line[..whole.start()]).map_err(|reason| {
+                anyhow::anyhow!(
+                    "Environment variable '{}' used by '{}' (line {}) {}",
+                    name,
+                    key(),
+                    index + 1,
+                    reason
+                )
+            })?;
+
+            interpolated.push_str(&line[last..whole.start()]);
+            interpolated.push_str(&value);
+            last = whole.end();
+        }
+        interpolated.push_str(&line[last..]);
+    }
+
+    Ok(interpolated)
+}
+
+// an environment variable's value as written into the config, given the
+// text of its line before it. inside a double quoted string it's escaped,
+// which works alike for toml and json. a single quoted toml string has no
+// escapes, and a bare value has to be a plain number, boolean or word
+fn quote_env_value(value: &str, before: &str) -> Result<String, String> {
+    let mut quote = None;
+    let mut chars = before.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some('"'), '\\') => {
+                chars.next();
+            }
+            (Some(open), c) if c == open => quote = None,
+            _ => {}
+        }
+    }
+
+    match quote {
+        Some('"') => Ok(value
+            .chars()
+            .map(|c| match c {
+                '"' => "\\\"".to_string(),
+                '\\' => "\\\\".to_string(),
+                '\n' => "\\n".to_string(),
+                '\r' => "\\r".to_string(),
+                '\t' => "\\t".to_string(),
+                c if c.is_control() => format!("\\u{:04x}", c as u32),
+                c => c.to_string(),
+            })
+            .collect()),
+        Some(_) if value.contains(['\'', '\n', '\r']) => {
+            Err("can't be put in a single quoted string".to_string())
+        }
+        Some(_) => Ok(value.to_string()),
+        None if !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_.+-".contains(c)) =>
+        {
+            Ok(value.to_string())
+        }
+        None => Err("has to be quoted, its value isn't a plain number or word".to_string()),
+    }
+}
+
+// the KEY=VALUE pairs of a .env style file. values may be double quoted
+// (with \n, \", \\ and \$ escapes), single quoted (taken literally) or
+// bare, where a # after whitespace starts a comment
+pub fn read_env_file(path: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let key_regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read the env file '{}'", path.display()))?;
+    let mut pairs = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let malformed = |reason: &str| {
+            anyhow::anyhow!(
+                "Malformed env file '{}' (line {}): {}",
+                path.display(),
+                index + 1,
+                reason
+            )
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| malformed("missing '='"))?;
+        let key = key.trim();
+        if !key_regex.is_match(key) {
+            return Err(malformed(&format!("invalid name '{}'", key)));
+        }
+
+        let value = value.trim_start();
+        let (value, rest) = match value.chars().next() {
+            Some('"') => {
+                let mut unquoted = String::new();
+                let mut chars = value[1..].char_indices();
+                let mut end = None;
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            end = Some(i + 2);
+                            break;
+                        }
+                        '\\' => match chars.next() {
+                            Some((_, 'n')) => unquoted.push('\n'),
+                            Some((_, c @ ('"' | '\\' | '$'))) => unquoted.push(c),
+                            Some((_, c)) => {
+                                unquoted.push('\\');
+                                unquoted.push(c);
+                            }
+                            None => break,
+                        },
+                        c => unquoted.push(c),
+                    }
+                }
+                let end = end.ok_or_else(|| malformed("unterminated double quote"))?;
+                (unquoted, &value[end..])
+            }
+            Some('\'') => {
+                let end = value[1..]
+                    .find('\'')
+                    .ok_or_else(|| malformed("unterminated single quote"))?;
+                (value[1..end + 1].to_string(), &value[end + 2..])
+            }
+            _ => {
+                let end = value
+                    .char_indices()
+                    .find(|&(i, c)| c == '#' && value[..i].ends_with(char::is_whitespace))
+                    .map_or(value.len(), |(i, _)| i);
+                (value[..end].trim_end().to_string(), "")
+            }
+        };
+
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(malformed("unexpected text after the closing quote"));
+        }
+
+        pairs.push((key.to_string(), value));
+    }
+
+    Ok(pairs)
+}
+
 pub fn read_config(config_arg: Option<String>) -> anyhow::Result<(Config, String)> {
-    let get_toml = || -> anyhow::Result<(String, String)> {
+    let get_config_str = || -> anyhow::Result<(String, String)> {
         if let Some(c) = config_arg {
-            return Ok((fs::read_to_string(&c)?, c));
+            let config_str = fs::read_to_string(&c)
+                .with_context(|| format!("Unable to read the config file '{}'", c))?;
+            return Ok((config_str, c));
         }
 
-        let root_config = Path::new("./barn.toml");
-        if root_config.exists() {
-            return Ok((
-                fs::read_to_string(root_config)?,
-                root_config.display().to_string(),
-            ));
+        // toml takes precedence over json when both are present
+        for file_name in ["barn.toml", "barn.json"] {
+            let root_config = Path::new(".").join(file_name);
+            if root_config.exists() {
+                return Ok((
+                    fs::read_to_string(&root_config)?,
+                    root_config.display().to_string(),
+                ));
+            }
         }
 
-        let home_config = dirs::config_dir()
-            .unwrap_or_default()
-            .join("barn")
-            .join("barn.toml");
-        if home_config.exists() {
-            return Ok((
-                fs::read_to_string(&home_config)?,
-                home_config.display().to_string(),
-            ));
+        for file_name in ["barn.toml", "barn.json"] {
+            let home_config = dirs::config_dir()
+                .unwrap_or_default()
+                .join("barn")
+                .join(file_name);
+            if home_config.exists() {
+                return Ok((
+                    fs::read_to_string(&home_config)?,
+                    home_config.display().to_string(),
+                ));
+            }
         }
 
         Ok((String::new(), "using defaults".to_string()))
     };
 
-    let (toml_str, config_location) = get_toml()?;
-    toml::from_str::<Config>(&toml_str)
-        .with_context(|| "Invalid config")
-        .map(|config| (config, config_location.to_string()))
+    let (config_str, config_location) = get_config_str()?;
+    let config_str = interpolate_env(&config_str)?;
+    // files named neither .json nor .toml are told apart by their content, a
+    // json config being an object while toml can't start with a brace
+    let is_json = match Path::new(&config_location).extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("json") => true,
+        Some(ext) if ext.eq_ignore_ascii_case("toml") => false,
+        _ => config_str.trim_start().starts_with('{'),
+    };
+
+    let config = if is_json {
+        serde_json::from_str::<Config>(&config_str).with_context(|| "Invalid config")?
+    } else {
+        toml::from_str::<Config>(&config_str).with_context(|| "Invalid config")?
+    };
+
+    let strict = config
+        .options
+        .password_policy
+        .as_ref()
+        .is_some_and(|policy| policy.strict);
+    let weak_passwords = config.weak_passwords();
+    if strict && !weak_passwords.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Weak passwords: {}",
+            weak_passwords.join(", ")
+        ));
+    }
+
+    if let Some(format) = &config.options.timestamp_format {
+        check_timestamp_format(format)?;
+    }
+
+    Ok((config, config_location))
 }
 
-pub fn log_config_information(config: &Config, root: &PathBuf) -> Result<(), anyhow::Error> {
+// chrono panics when asked to format with an invalid specifier, so the
+// format is rejected up front rather than on the first timestamped line
+fn check_timestamp_format(format: &str) -> anyhow::Result<()> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(anyhow::anyhow!("Invalid timestamp_format '{}'", format));
+    }
+
+    Ok(())
+}
+
+// everything about the config that's worth a warning at startup. the strict
+// options turn some of them into errors. `discovered` is what
+// options.discovery_command listed, if it's set
+fn config_warnings(
+    config: &Config,
+    root: &Path,
+    discovered: Option<&[String]>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut warnings = Vec::new();
+
+    if config.options.auth == AuthMode::None {
+        warnings.push(
+            "auth is set to \"none\", anyone who can reach barn can run every executable"
+                .bold()
+                .red()
+                .to_string(),
+        );
+    }
+
     // log a warning if a user is assigned a non-existent group
-    let mut had_warns = false;
     let valid_groups = config
         .group
         .iter()
@@ -138,26 +1264,112 @@ pub fn log_config_information(config: &Config, root: &PathBuf) -> Result<(), any
     for user in config.user.iter() {
         for group in user.groups.iter() {
             if !valid_groups.contains(&group) {
-                had_warns = true;
-                println!(
-                    "{} the user '{}' has been assigned a non-existent group '{}'",
-                    "[warn]".bold().yellow(),
-                    user.username,
-                    group
-                )
+                warnings.push(format!(
+                    "the user '{}' has been assigned a non-existent group '{}'",
+                    user.username, group
+                ));
             }
         }
     }
 
-    if had_warns {
-        println!("");
+    for group in config.options.default_groups.iter() {
+        if !valid_groups.contains(&group) {
+            warnings.push(format!(
+                "the non-existent group '{}' is listed in default_groups",
+                group
+            ));
+        }
+    }
+
+    for weakness in config.weak_passwords() {
+        warnings.push(weakness);
+    }
+
+    for route in config.route.iter() {
+        if !root.join(&route.executable).is_file() {
+            warnings.push(format!(
+                "the route '{}' points to the non-existent executable '{}'",
+                route.path, route.executable
+            ));
+        }
+    }
+
+    // a group that matches nothing is most likely a typo in its regex. the
+    // admin group is left alone, it's often there only for the endpoints.
+    // the groups are matched against the same executables the index lists
+    let executables = list_executables(root)?;
+    let listed = discovered.unwrap_or(&executables);
+    for group in config
+        .group
+        .iter()
+        .filter(|group| group.name != ADMIN_GROUP)
+    {
+        if listed.iter().any(|name| group.regex.is_match(name)) {
+            continue;
+        }
+
+        if config.options.strict_group_regexes {
+            return Err(anyhow::anyhow!(
+                "the regex of the group '{}' doesn't match any executable in the root",
+                group.name
+            ));
+        }
+
+        warnings.push(format!(
+            "the regex of the group '{}' doesn't match any executable in the root",
+            group.name
+        ));
+    }
+
+    // a giant binary in the root is more likely a mistake than an endpoint
+    if let Some(max_size) = config.options.max_executable_size {
+        for name in &executables {
+            let size = fs::metadata(root.join(name))?.len();
+            if size <= max_size {
+                continue;
+            }
+
+            if config.options.strict_executable_size {
+                return Err(anyhow::anyhow!(
+                    "'{}' is {} bytes, more than max_executable_size ({} bytes)",
+                    name,
+                    size,
+                    max_size
+                ));
+            }
+
+            warnings.push(format!(
+                "'{}' is {} bytes, more than max_executable_size ({} bytes)",
+                name, size, max_size
+            ));
+        }
+    }
+
+    // these are refused when requested, but a heads up beats a 403
+    for name in &executables {
+        if let Some(reason) = untrusted_executable(&root.join(name), &config.options) {
+            warnings.push(format!("'{}' won't be run: {}", name, reason));
+        }
+    }
+
+    Ok(warnings)
+}
+
+pub fn log_config_information(
+    config: &Config,
+    root: &PathBuf,
+    discovered: Option<&[String]>,
+) -> Result<(), anyhow::Error> {
+    let warnings = config_warnings(config, root, discovered)?;
+    for warning in &warnings {
+        println!("{} {}", "[warn]".bold().yellow(), warning);
+    }
+    if !warnings.is_empty() {
+        println!();
     }
 
     // log the groups which can execute executables in the executables' root
-    let executables: Vec<DirEntry> = read_dir(root)?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry)
-        .collect();
+    let executables: Vec<DirEntry> = read_dir(root)?.filter_map(|entry| entry.ok()).collect();
 
     println!("{}", "Groups allowed to run: ".blue().bold());
     for executable in executables.iter() {
@@ -205,5 +1417,284 @@ pub fn log_config_information(config: &Config, root: &PathBuf) -> Result<(), any
         );
     }
 
+    if list_executables(root)?.is_empty() {
+        println!(
+            "\n{} no runnable executables found in '{}', every request will fail until some are added",
+            "[warn]".bold().yellow(),
+            root.display()
+        );
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRoot;
+
+    #[test]
+    fn resource_thresholds_share_a_unit() {
+        let options = |toml: &str| toml::from_str::<Config>(toml).unwrap().options;
+
+        let sized = options("[options]\nmemory_guard = \"512M\"\nmin_free_disk = \"512M\"\n");
+        assert_eq!(sized.memory_guard, Some(512 << 20));
+        assert_eq!(sized.min_free_disk, Some(512 << 20));
+
+        let plain = options("[options]\nmemory_guard = 1024\nmin_free_disk = 1024\n");
+        assert_eq!(plain.memory_guard, Some(1024));
+        assert_eq!(plain.min_free_disk, Some(1024));
+
+        let huge = options("[options]\nmemory_guard = \"99999999999T\"\n");
+        assert_eq!(huge.memory_guard, Some(u64::MAX));
+    }
+
+    #[test]
+    fn json_configs_match_toml_ones() {
+        let dir = TestRoot::new();
+        let toml = dir.file(
+            "barn.toml",
+            r#"
+            [options]
+            port = 9000
+            timestamp_lines = true
+
+            [[user]]
+            username = "user"
+            password = "password"
+            groups = ["admins"]
+
+            [[group]]
+            name = "admins"
+            regex = "^backup"
+            "#,
+        );
+        let json = r#"{
+            "options": { "port": 9000, "timestamp_lines": true },
+            "user": [{ "username": "user", "password": "password", "groups": ["admins"] }],
+            "group": [{ "name": "admins", "regex": "^backup" }]
+        }"#;
+
+        let (from_toml, _) = read_config(Some(toml.display().to_string())).unwrap();
+        let by_extension = dir.file("barn.json", json);
+        let (from_json, _) = read_config(Some(by_extension.display().to_string())).unwrap();
+        assert_eq!(format!("{:?}", from_toml), format!("{:?}", from_json));
+
+        // without an extension, the content gives json away
+        let by_content = dir.file("barn-config", json);
+        let (from_json, _) = read_config(Some(by_content.display().to_string())).unwrap();
+        assert_eq!(format!("{:?}", from_toml), format!("{:?}", from_json));
+    }
+
+    #[test]
+    fn timestamp_format_is_validated() {
+        assert!(check_timestamp_format("%H:%M:%S%.3f").is_ok());
+        assert!(check_timestamp_format("[%Y-%m-%d]").is_ok());
+        assert!(check_timestamp_format("%H:%Q").is_err());
+        assert!(check_timestamp_format("%").is_err());
+    }
+
+    #[test]
+    fn disabled_auth_is_warned_about() {
+        let root = TestRoot::new();
+        let warnings = |toml: &str| config_warnings(&root.config(toml), root.path(), None).unwrap();
+
+        assert!(warnings("")
+            .iter()
+            .all(|warning| !warning.contains("auth is set to \"none\"")));
+        assert!(warnings("[options]\nauth = \"none\"\n")
+            .iter()
+            .any(|warning| warning.contains("auth is set to \"none\"")));
+    }
+
+    #[test]
+    fn environment_variables_are_interpolated() {
+        let dir = TestRoot::new();
+        std::env::set_var("BARN_TEST_PORT", "9123");
+        std::env::set_var("BARN_TEST_ROOT", dir.path());
+        let path = dir.file(
+            "barn.toml",
+            "[options]\n# ${BARN_TEST_UNSET} is fine in a comment\nport = ${BARN_TEST_PORT}\nroot = \"${BARN_TEST_ROOT}\"\n",
+        );
+
+        let (config, _) = read_config(Some(path.display().to_string())).unwrap();
+        assert_eq!(config.options.port, 9123);
+        assert_eq!(config.options.root, dir.path());
+
+        dir.file("barn.toml", "[options]\nport = ${BARN_TEST_UNSET}\n");
+        let error = read_config(Some(path.display().to_string())).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Environment variable 'BARN_TEST_UNSET' used by 'port' (line 2) is not set"
+        );
+    }
+
+    #[test]
+    fn interpolated_values_stay_inside_their_strings() {
+        let dir = TestRoot::new();
+        let sneaky = "pass\"\nport = 1\n\\";
+        std::env::set_var("BARN_TEST_SNEAKY", sneaky);
+        let path = dir.file(
+            "barn.toml",
+            "[options]\nport = 9123\n\n[[user]]\nusername = \"u\"\npassword = \"${BARN_TEST_SNEAKY}\"\ngroups = []\n",
+        );
+        let (config, _) = read_config(Some(path.display().to_string())).unwrap();
+        assert_eq!(config.options.port, 9123);
+        assert_eq!(config.user[0].password, sneaky);
+
+        let path = dir.file(
+            "barn.json",
+            "{\"options\": {\"port\": 9123}, \"user\": [{\"username\": \"u\", \"password\": \"${BARN_TEST_SNEAKY}\", \"groups\": []}]}\n",
+        );
+        let (config, _) = read_config(Some(path.display().to_string())).unwrap();
+        assert_eq!(config.options.port, 9123);
+        assert_eq!(config.user[0].password, sneaky);
+
+        dir.file("barn.toml", "[options]\nhost = ${BARN_TEST_SNEAKY}\n");
+        let error =
+            read_config(Some(dir.path().join("barn.toml").display().to_string())).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Environment variable 'BARN_TEST_SNEAKY' used by 'host' (line 2) has to be quoted, its value isn't a plain number or word"
+        );
+    }
+
+    #[test]
+    fn compression_levels_are_parsed_and_bounded() {
+        let options = |toml: &str| toml::from_str::<Config>(toml).map(|config| config.options);
+
+        assert_eq!(
+            options("").unwrap().compression_level,
+            default_compression_level()
+        );
+        assert_eq!(
+            options("[options]\ncompression_level = 9\n")
+                .unwrap()
+                .compression_level,
+            9
+        );
+        let error = options("[options]\ncompression_level = 10\n").unwrap_err();
+        assert!(error.to_string().contains("gzip takes 0 to 9"), "{}", error);
+    }
+
+    #[test]
+    fn weak_passwords_are_warned_about_or_refused() {
+        let root = TestRoot::new();
+        let users = "[[user]]\nusername = \"alice\"\npassword = \"abc\"\ngroups = []\n\
+                     [[user]]\nusername = \"bob\"\npassword = \"Correct-Horse-42\"\ngroups = []\n";
+        let policy = "[options.password_policy]\nmin_length = 12\ncomplexity = true\n";
+
+        let config = root.config(&format!("{}{}", users, policy));
+        let warnings = config_warnings(&config, root.path(), None).unwrap();
+        assert!(warnings
+            .iter()
+            .any(|warning| warning == "the password of 'alice' is shorter than 12 characters"));
+        assert!(warnings.iter().all(|warning| !warning.contains("'bob'")));
+
+        let strict = root.file(
+            "strict.toml",
+            &format!("{}{}strict = true\n", users, policy),
+        );
+        let error = read_config(Some(strict.display().to_string())).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Weak passwords: the password of 'alice' is shorter than 12 characters"
+        );
+    }
+
+    #[test]
+    fn env_files_are_parsed_like_a_shell_would() {
+        let root = TestRoot::new();
+        let env_file = root.file(
+            "app.env",
+            concat!(
+                "# deployment settings\n",
+                "export TARGET=production # the live one\n",
+                "GREETING=\"hello \\\"world\\\"\"\n",
+                "PATTERN='$HOME #literal'\n",
+                "\n",
+                "EMPTY=\n",
+            ),
+        );
+        assert_eq!(
+            read_env_file(&env_file).unwrap(),
+            [
+                ("TARGET".to_string(), "production".to_string()),
+                ("GREETING".to_string(), "hello \"world\"".to_string()),
+                ("PATTERN".to_string(), "$HOME #literal".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+
+        let malformed = root.file("bad.env", "OK=1\nNAME=\"unterminated\n");
+        let error = read_env_file(&malformed).unwrap_err().to_string();
+        assert!(
+            error.contains("(line 2): unterminated double quote"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn oversized_executables_are_warned_about_or_refused() {
+        let root = TestRoot::new();
+        root.script("small.sh", "true");
+        root.script("big.sh", &format!("true\n#{}", "x".repeat(2048)));
+        let config = |strict: bool| {
+            root.config(&format!(
+                "[options]\nmax_executable_size = 1024\nstrict_executable_size = {}\n",
+                strict
+            ))
+        };
+
+        let warnings = config_warnings(&config(false), root.path(), None).unwrap();
+        let oversized = warnings
+            .iter()
+            .filter(|warning| warning.contains("more than max_executable_size"))
+            .collect::<Vec<_>>();
+        assert_eq!(oversized.len(), 1);
+        assert!(
+            oversized[0].starts_with("'big.sh' is 2065 bytes"),
+            "{}",
+            oversized[0]
+        );
+
+        let error = config_warnings(&config(true), root.path(), None).unwrap_err();
+        assert!(
+            error.to_string().starts_with("'big.sh' is 2065 bytes"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn groups_matching_nothing_are_warned_about() {
+        let root = TestRoot::new();
+        root.script("deploy.sh", "true");
+        let groups = "[[group]]\nname = \"deployers\"\nregex = \"^deploy\"\n\
+                      [[group]]\nname = \"typo\"\nregex = \"^delpoy\"\n";
+        let unmatched = "the regex of the group 'typo' doesn't match any executable in the root";
+
+        let warnings = config_warnings(&root.config(groups), root.path(), None).unwrap();
+        assert!(warnings.iter().any(|warning| warning == unmatched));
+        assert!(warnings
+            .iter()
+            .all(|warning| !warning.contains("'deployers'")));
+
+        let strict = root.config(&format!(
+            "[options]\nstrict_group_regexes = true\n{}",
+            groups
+        ));
+        let error = config_warnings(&strict, root.path(), None).unwrap_err();
+        assert_eq!(error.to_string(), unmatched);
+
+        // with a discovery command, its list is what the groups have to match
+        let discovered = ["delpoy.sh".to_string()];
+        let warnings =
+            config_warnings(&root.config(groups), root.path(), Some(&discovered)).unwrap();
+        assert!(warnings.iter().all(|warning| warning != unmatched));
+        assert!(warnings
+            .iter()
+            .any(|warning| warning.contains("'deployers'")));
+    }
+}
@@ -17,10 +17,44 @@ lazy_static! {
     );
 }
 
-pub static VIEWER_TEMPLATE: &[u8] = include_bytes!("viewer.html");
+// query parameters interpreted by barn itself rather than the executable
+pub static RESERVED_PARAMS: &[&str] = &["raw", "run", "stream", "theme"];
+
+pub static VIEWER_TEMPLATE: &str = include_str!("viewer.html");
+pub static OFFLINE_ASSETS: &str = include_str!("offline.css");
 lazy_static! {
-    pub static ref VIEWER_TEMPLATE_STR: String =
-        String::from_utf8(VIEWER_TEMPLATE.to_vec()).unwrap();
-    pub static ref VIEWER_TEMPLATE_BYTES: Bytes = Bytes::from_static(VIEWER_TEMPLATE);
+    pub static ref VIEWER_TEMPLATE_STR: String = viewer_template(cfg!(feature = "offline"));
+    pub static ref VIEWER_TEMPLATE_BYTES: Bytes = Bytes::from(VIEWER_TEMPLATE_STR.clone());
     pub static ref VIEWER_ENDING_BYTES: Bytes = Bytes::from_static(b"</div> </body> </html>");
 }
+
+// offline builds swap the external assets for inlined styles that only use a
+// local font, so the viewer renders the same without internet access
+fn viewer_template(offline: bool) -> String {
+    if !offline {
+        return VIEWER_TEMPLATE.to_string();
+    }
+
+    let inlined = format!("\n    <style>\n{}    </style>", OFFLINE_ASSETS);
+    Regex::new(r"(?s)\s*<!-- external assets -->.*?<!-- end external assets -->")
+        .unwrap()
+        .replace_all(VIEWER_TEMPLATE, regex::NoExpand(&inlined))
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_viewers_inline_their_assets() {
+        let online = viewer_template(false);
+        assert!(online.contains("https://fonts.googleapis.com"));
+
+        let offline = viewer_template(true);
+        assert!(!offline.contains("https://"));
+        assert!(!offline.contains("<link"));
+        assert!(offline.contains(OFFLINE_ASSETS));
+        assert!(offline.contains("<style>\n/* served in place of the external assets"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use colored::Colorize;
+use tokio::{process::Command, sync::Mutex, time::timeout};
+
+use crate::{config::Options, constants::FILENAME_REGEX};
+
+// how long the discovery command gets to print its list
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the executables options.discovery_command says exist, re-asked for once
+// options.discovery_ttl has passed
+#[derive(Default)]
+pub struct Discovery {
+    // held while the command runs, so concurrent requests wait for one run
+    // instead of each starting their own
+    cache: Mutex<Option<(Instant, Vec<String>)>>,
+}
+
+impl Discovery {
+    // None when executables aren't discovered but found in the root. if the
+    // command fails, the last list it printed is kept in use
+    pub async fn names(&self, options: &Options) -> Result<Option<Vec<String>>, String> {
+        let Some(command) = &options.discovery_command else {
+            return Ok(None);
+        };
+
+        let mut cache = self.cache.lock().await;
+        let ttl = Duration::from_secs(options.discovery_ttl);
+        if let Some((fetched, names)) = &*cache {
+            if fetched.elapsed() < ttl {
+                return Ok(Some(names.clone()));
+            }
+        }
+
+        match run_discovery(command).await {
+            Ok(names) => {
+                *cache = Some((Instant::now(), names.clone()));
+                Ok(Some(names))
+            }
+            Err(e) => match &*cache {
+                Some((_, names)) => {
+                    println!(
+                        "{} the discovery command {}, using the previous list",
+                        "[warn]".bold().yellow(),
+                        e
+                    );
+                    Ok(Some(names.clone()))
+                }
+                None => Err(format!("the discovery command {}", e)),
+            },
+        }
+    }
+}
+
+// one name per line. blank lines and names barn wouldn't accept in a path
+// are skipped
+async fn run_discovery(command: &str) -> Result<Vec<String>, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+
+    let output = match timeout(DISCOVERY_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => output,
+        Ok(Ok(output)) => return Err(format!("exited with {}", output.status)),
+        Ok(Err(e)) => return Err(format!("could not be spawned: {}", e)),
+        Err(_) => {
+            return Err(format!(
+                "timed out after {} seconds",
+                DISCOVERY_TIMEOUT.as_secs()
+            ))
+        }
+    };
+
+    let mut names = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|name| FILENAME_REGEX.is_match(name))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use crate::testing::{app, basic_auth, body_text, TestRoot, USER};
+
+    #[actix_web::test]
+    async fn discovered_names_are_listed_and_runnable() {
+        let root = TestRoot::new();
+        root.script("alpha.sh", "echo alpha");
+        root.script("beta.sh", "echo beta");
+        root.script("undiscovered.sh", "echo undiscovered");
+        let config = root.config(&format!(
+            "[options]\ndiscovery_command = \"printf 'alpha.sh\\\\nbeta.sh\\\\n'\"\n{}",
+            USER
+        ));
+        let app = app(config).await;
+        let request = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(basic_auth("user", "password"))
+                .to_request()
+        };
+
+        let index = body_text(test::call_service(&app, request("/")).await).await;
+        assert!(index.contains("<a href=\"/alpha.sh\">"), "{}", index);
+        assert!(index.contains("<a href=\"/beta.sh\">"), "{}", index);
+        assert!(!index.contains("undiscovered.sh"));
+
+        for name in ["alpha", "beta"] {
+            let response = test::call_service(&app, request(&format!("/{}.sh?raw", name))).await;
+            assert_eq!(body_text(response).await, format!("{}\n", name));
+        }
+        let refused = test::call_service(&app, request("/undiscovered.sh?raw")).await;
+        assert_eq!(refused.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{
+    config::{Executable, FieldType},
+    constants::VIEWER_TEMPLATE_STR,
+    output::escape_html,
+};
+
+// a form for the executable's configured parameters. submitting it comes
+// back to the executable with ?run set, which runs it with the fields as
+// --name value flags, unless options.query_args says how to pass them
+pub fn form_page(executable: &Executable) -> String {
+    let description = executable
+        .description
+        .as_ref()
+        .map(|description| {
+            format!(
+                "<p class=\"description\">{}</p>\n",
+                escape_html(description)
+            )
+        })
+        .unwrap_or_default();
+
+    let fields = executable
+        .form
+        .iter()
+        .map(|field| {
+            let name = escape_html(&field.name);
+            let label = escape_html(field.label.as_deref().unwrap_or(&field.name));
+            let input = match field.field_type {
+                FieldType::Text => format!("<input type=\"text\" name=\"{}\" />", name),
+                FieldType::Number => format!("<input type=\"number\" name=\"{}\" />", name),
+                // unchecked boxes aren't submitted at all, checked ones as "true"
+                FieldType::Checkbox => {
+                    format!(
+                        "<input type=\"checkbox\" name=\"{}\" value=\"true\" />",
+                        name
+                    )
+                }
+            };
+
+            format!("<label>{} {}</label>\n", label, input)
+        })
+        .collect::<String>();
+
+    format!(
+        concat!(
+            "{}{}",
+            "<form class=\"form\" method=\"get\" action=\"/{}\">\n",
+            "{}",
+            "<input type=\"hidden\" name=\"run\" value=\"1\" />\n",
+            "<button type=\"submit\">Run</button>\n",
+            "</form>\n",
+            "</div> </body> </html>"
+        ),
+        *VIEWER_TEMPLATE_STR,
+        description,
+        escape_html(&executable.name),
+        fields
+    )
+}
@@ -0,0 +1,208 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+
+use crate::{
+    config::AuthMode,
+    constants::VIEWER_TEMPLATE_STR,
+    output::escape_html,
+    utils::{authenticate, can_run, list_executables, IntoHttpError},
+    BarnState,
+};
+
+// lists the executables in the root that the user may run, linking to each
+// of them. the names alone say a lot, so the listing is only shown to those
+// who can log in
+pub async fn index_handler(
+    req: HttpRequest,
+    creds: Option<BasicAuth>,
+    data: web::Data<BarnState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let config = data.config();
+    let user = match config.options.auth {
+        AuthMode::None => None,
+        _ => Some(authenticate(&req, creds, &config)?),
+    };
+
+    let discovered = data
+        .discovery
+        .names(&config.options)
+        .await
+        .templated_error(
+            "Unable to discover the executables",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )?;
+    let executables = match discovered {
+        Some(names) => names,
+        None => list_executables(&config.options.root).templated_error(
+            "Unable to list the executables' root",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )?,
+    };
+    let executables = executables
+        .into_iter()
+        .filter(|name| !config.is_hidden(name) && can_run(&config, user, name))
+        .collect::<Vec<_>>();
+
+    let listing = if executables.is_empty() {
+        concat!(
+            "<p class=\"warning\">No executables are available yet.</p>\n",
+            "<pre class=\"stdout\">Add executable files to barn's executables' root ",
+            "and they will show up here.</pre>\n"
+        )
+        .to_string()
+    } else {
+        executables
+            .iter()
+            .map(|name| {
+                let executable = config.executable(name);
+                let description = executable
+                    .and_then(|executable| executable.description.as_ref())
+                    .map(|description| {
+                        format!(
+                            " <span class=\"description\">{}</span>",
+                            escape_html(description)
+                        )
+                    })
+                    .unwrap_or_default();
+                let args_help = executable
+                    .and_then(|executable| executable.args_help.as_ref())
+                    .map(|args_help| {
+                        format!(
+                            " <span class=\"args-help\">{}</span>",
+                            escape_html(args_help)
+                        )
+                    })
+                    .unwrap_or_default();
+
+                format!(
+                    "<pre class=\"stdout\"><a href=\"/{0}\">{0}</a>{1}{2}</pre>\n",
+                    escape_html(name),
+                    description,
+                    args_help
+                )
+            })
+            .collect()
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "{}{}{}",
+            *VIEWER_TEMPLATE_STR, listing, "</div> </body> </html>"
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use crate::testing::{app, basic_auth, body_text, TestRoot, USER};
+
+    #[actix_web::test]
+    async fn the_index_is_only_shown_to_users() {
+        let root = TestRoot::new();
+        root.script("secret.sh", "echo secret");
+        let app = app(root.config(USER)).await;
+
+        let request = test::TestRequest::get().uri("/").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!body_text(response).await.contains("secret.sh"));
+
+        let request = test::TestRequest::get()
+            .uri("/")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        assert!(body_text(test::call_service(&app, request).await)
+            .await
+            .contains("<a href=\"/secret.sh\">secret.sh</a>"));
+    }
+
+    #[actix_web::test]
+    async fn an_empty_root_is_explained() {
+        let root = TestRoot::new();
+        let app = app(root.config("[options]\nauth = \"none\"\n")).await;
+
+        let request = test::TestRequest::get().uri("/").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_text(response)
+            .await
+            .contains("No executables are available yet."));
+    }
+
+    #[actix_web::test]
+    async fn users_only_see_what_they_may_run() {
+        let root = TestRoot::new();
+        root.script("mine.sh", "echo mine");
+        root.script("theirs.sh", "echo theirs");
+        root.script("unlisted.sh", "echo unlisted");
+        let app = app(root.config(concat!(
+            "[[user]]\nusername = \"user\"\npassword = \"password\"\ngroups = [\"mine\"]\n",
+            "[[group]]\nname = \"mine\"\nregex = \"^(mine|unlisted)\"\n",
+            "[[group]]\nname = \"theirs\"\nregex = \"^theirs\"\n",
+            "[[executable]]\nname = \"unlisted.sh\"\nhidden = true\n",
+        )))
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let listing = body_text(test::call_service(&app, request).await).await;
+        assert!(listing.contains("mine.sh"));
+        assert!(!listing.contains("theirs.sh"));
+        assert!(!listing.contains("unlisted.sh"));
+
+        // hidden executables are still runnable directly
+        let request = test::TestRequest::get()
+            .uri("/unlisted.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        assert_eq!(
+            body_text(test::call_service(&app, request).await).await,
+            "unlisted\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn descriptions_are_listed() {
+        let root = TestRoot::new();
+        root.script("backup.sh", "echo backing up");
+        let app = app(root.config(&format!(
+            "{}\n[[executable]]\nname = \"backup.sh\"\ndescription = \"Backs up <everything>\"\n",
+            USER
+        )))
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        assert!(body_text(test::call_service(&app, request).await)
+            .await
+            .contains("<span class=\"description\">Backs up &lt;everything&gt;</span>"));
+    }
+
+    #[actix_web::test]
+    async fn argument_hints_are_listed() {
+        let root = TestRoot::new();
+        root.script("backup.sh", "echo backing up");
+        let app = app(root.config(&format!(
+            "{}\n[[executable]]\nname = \"backup.sh\"\nargs_help = \"?arg=--full&arg=<dir>\"\n",
+            USER
+        )))
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let body = body_text(test::call_service(&app, request).await).await;
+        assert!(
+            body.contains("<span class=\"args-help\">?arg=--full&amp;arg=&lt;dir&gt;</span>"),
+            "{}",
+            body
+        );
+    }
+}
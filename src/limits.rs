@@ -0,0 +1,397 @@
+use futures::{Stream, StreamExt};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+
+// weight given to the latest execution when updating the average duration
+const DURATION_SMOOTHING: f64 = 0.2;
+
+pub struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    average_duration: Arc<Mutex<Option<Duration>>>,
+    queue: Arc<Mutex<FairQueue>>,
+}
+
+pub struct ExecutionPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    started: Option<Instant>,
+    average_duration: Arc<Mutex<Option<Duration>>>,
+    queue: Arc<Mutex<FairQueue>>,
+}
+
+// requests waiting for a slot, keyed by user. freed slots go round-robin
+// across the users rather than to whoever queued first, so one user's burst
+// can't starve everyone else
+#[derive(Default)]
+struct FairQueue {
+    turns: VecDeque<String>,
+    waiting: HashMap<String, VecDeque<oneshot::Sender<OwnedSemaphorePermit>>>,
+}
+
+impl FairQueue {
+    fn push(&mut self, user: &str, sender: oneshot::Sender<OwnedSemaphorePermit>) {
+        let waiting = self.waiting.entry(user.to_string()).or_default();
+        if waiting.is_empty() {
+            self.turns.push_back(user.to_string());
+        }
+        waiting.push_back(sender);
+    }
+
+    // gives the slot to the user whose turn it is. waiters that gave up are
+    // skipped, and the slot is released if nobody is left to take it
+    fn hand_off(&mut self, mut permit: OwnedSemaphorePermit) {
+        while let Some(user) = self.turns.pop_front() {
+            let waiting = self.waiting.get_mut(&user).unwrap();
+            let sender = waiting.pop_front().unwrap();
+            if waiting.is_empty() {
+                self.waiting.remove(&user);
+            } else {
+                self.turns.push_back(user);
+            }
+
+            match sender.send(permit) {
+                Ok(()) => return,
+                Err(returned) => permit = returned,
+            }
+        }
+    }
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max_concurrent: usize) -> Self {
+        ConcurrencyLimit {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            average_duration: Arc::new(Mutex::new(None)),
+            queue: Arc::new(Mutex::new(FairQueue::default())),
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<ExecutionPermit> {
+        let permit = self.semaphore.clone().try_acquire_owned().ok()?;
+        Some(self.permit(permit))
+    }
+
+    // waits up to max_wait in the fair queue for a slot
+    pub async fn acquire(&self, user: &str, max_wait: Duration) -> Option<ExecutionPermit> {
+        let (sender, mut receiver) = oneshot::channel();
+        {
+            let mut queue = self.queue.lock().unwrap();
+            queue.push(user, sender);
+            // slots can also be free because a waiter left after being
+            // handed one, so anything available goes out in turn order
+            while let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+                if queue.turns.is_empty() {
+                    break;
+                }
+                queue.hand_off(permit);
+            }
+        }
+
+        let permit = match tokio::time::timeout(max_wait, &mut receiver).await {
+            Ok(permit) => permit.ok()?,
+            Err(_) => {
+                // a slot may have been handed over just as the wait ran out
+                receiver.close();
+                receiver.try_recv().ok()?
+            }
+        };
+        Some(self.permit(permit))
+    }
+
+    fn permit(&self, permit: OwnedSemaphorePermit) -> ExecutionPermit {
+        ExecutionPermit {
+            permit: Some(permit),
+            started: Some(Instant::now()),
+            average_duration: self.average_duration.clone(),
+            queue: self.queue.clone(),
+        }
+    }
+
+    // estimated seconds until a slot frees up, based on how long executions
+    // have been taking on average
+    pub fn retry_after(&self) -> u64 {
+        let average = *self.average_duration.lock().unwrap();
+        average
+            .map(|duration| duration.as_secs_f64().ceil() as u64)
+            .unwrap_or(1)
+            .max(1)
+    }
+}
+
+impl ExecutionPermit {
+    // gives the slot back without a run to count towards the average duration
+    pub fn abandon(mut self) {
+        self.started = None;
+    }
+}
+
+impl Drop for ExecutionPermit {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            self.queue.lock().unwrap().hand_off(permit);
+        }
+
+        let elapsed = match self.started {
+            Some(started) => started.elapsed(),
+            None => return,
+        };
+        let mut average = self.average_duration.lock().unwrap();
+
+        *average = Some(match *average {
+            Some(previous) => {
+                previous.mul_f64(1.0 - DURATION_SMOOTHING) + elapsed.mul_f64(DURATION_SMOOTHING)
+            }
+            None => elapsed,
+        });
+    }
+}
+
+// caps how many clients may be streaming the same executable at once
+pub struct ViewerLimit {
+    max_viewers: usize,
+    active: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+pub struct ViewerGuard {
+    executable: String,
+    active: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl ViewerLimit {
+    pub fn new(max_viewers: usize) -> Self {
+        ViewerLimit {
+            max_viewers,
+            active: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn try_join(&self, executable: &str) -> Option<ViewerGuard> {
+        let mut active = self.active.lock().unwrap();
+        let viewers = active.entry(executable.to_string()).or_insert(0);
+        if *viewers >= self.max_viewers {
+            return None;
+        }
+
+        *viewers += 1;
+        Some(ViewerGuard {
+            executable: executable.to_string(),
+            active: self.active.clone(),
+        })
+    }
+}
+
+impl Drop for ViewerGuard {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(viewers) = active.get_mut(&self.executable) {
+            *viewers -= 1;
+            if *viewers == 0 {
+                active.remove(&self.executable);
+            }
+        }
+    }
+}
+
+// spaces spawns out evenly to options.spawn_rate per second. each spawn
+// reserves the next free slot, waiting for it unless that's too far off
+pub struct SpawnRate {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl SpawnRate {
+    pub fn new(per_second: f64) -> Self {
+        SpawnRate {
+            interval: Duration::from_secs_f64(1.0 / per_second.max(0.001)),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    // how long to wait before spawning, None if it'd be longer than max_wait
+    pub fn reserve(&self, max_wait: Duration) -> Option<Duration> {
+        let now = Instant::now();
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let slot = (*next_slot).max(now);
+        let wait = slot - now;
+        if wait > max_wait {
+            return None;
+        }
+
+        *next_slot = slot + self.interval;
+        Some(wait)
+    }
+
+    // the rate as a bucket of spawns. it holds as many as can be queued up
+    // within max_wait and refills by one every interval, so it's full again
+    // once the last reserved slot has come
+    pub fn state(&self, max_wait: Duration) -> RateLimitState {
+        let backlog = self
+            .next_slot
+            .lock()
+            .unwrap()
+            .saturating_duration_since(Instant::now());
+        let slots =
+            |wait: Duration| (wait.as_secs_f64() / self.interval.as_secs_f64()).floor() as u64 + 1;
+
+        RateLimitState {
+            limit: slots(max_wait),
+            remaining: max_wait.checked_sub(backlog).map_or(0, slots),
+            reset: backlog,
+        }
+    }
+}
+
+pub struct RateLimitState {
+    pub limit: u64,
+    pub remaining: u64,
+    // until the bucket is full again
+    pub reset: Duration,
+}
+
+// bytes of memory used by barn and its children, as accounted by the
+// cgroup barn runs in, or barn's own resident set outside of one
+pub fn memory_usage() -> Option<u64> {
+    let read = |path: &str| std::fs::read_to_string(path).ok();
+
+    for cgroup_file in [
+        "/sys/fs/cgroup/memory.current",
+        "/sys/fs/cgroup/memory/memory.usage_in_bytes",
+    ] {
+        if let Some(usage) = read(cgroup_file).and_then(|usage| usage.trim().parse().ok()) {
+            return Some(usage);
+        }
+    }
+
+    read("/proc/self/status")?
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|rss| rss.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kilobytes| kilobytes.saturating_mul(1024))
+}
+
+// bytes available to unprivileged users on the filesystem holding path
+#[cfg(unix)]
+pub fn free_disk_space(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return None;
+    }
+
+    // the field types vary between platforms
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Some((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn free_disk_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+// mutual exclusion between executions that share a lock name
+#[derive(Default)]
+pub struct NamedLocks {
+    held: Arc<Mutex<HashSet<String>>>,
+}
+
+pub struct LockGuard {
+    name: String,
+    held: Arc<Mutex<HashSet<String>>>,
+}
+
+impl NamedLocks {
+    pub fn try_lock(&self, name: &str) -> Option<LockGuard> {
+        if !self.held.lock().unwrap().insert(name.to_string()) {
+            return None;
+        }
+
+        Some(LockGuard {
+            name: name.to_string(),
+            held: self.held.clone(),
+        })
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        self.held.lock().unwrap().remove(&self.name);
+    }
+}
+
+// keeps `guard` alive for as long as the stream is being consumed
+pub fn guarded<S, G>(stream: S, guard: G) -> impl Stream<Item = S::Item>
+where
+    S: Stream,
+{
+    stream.map(move |item| {
+        let _guard = &guard;
+        item
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawn_rate_state_counts_down_with_reservations() {
+        let spawn_rate = SpawnRate::new(10.0);
+        let max_wait = Duration::from_secs(1);
+
+        let full = spawn_rate.state(max_wait);
+        assert_eq!((full.limit, full.remaining), (11, 11));
+        assert_eq!(full.reset, Duration::ZERO);
+
+        for remaining in (0..11).rev() {
+            assert!(spawn_rate.reserve(max_wait).is_some());
+            assert_eq!(spawn_rate.state(max_wait).remaining, remaining);
+        }
+        assert!(spawn_rate.reserve(max_wait).is_none());
+        assert!(spawn_rate.state(max_wait).reset > max_wait);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn free_disk_space_is_measured() {
+        let free = free_disk_space(&std::env::temp_dir()).unwrap();
+        assert!(free > 0);
+        assert_eq!(free_disk_space(Path::new("/does/not/exist")), None);
+    }
+
+    #[actix_web::test]
+    async fn freed_slots_take_turns_across_users() {
+        let limit = Arc::new(ConcurrencyLimit::new(1));
+        let holder = limit.try_acquire().unwrap();
+        let (granted, mut grants) = tokio::sync::mpsc::unbounded_channel();
+
+        // a burst from one user, queued ahead of two others
+        for user in ["heavy", "heavy", "heavy", "heavy", "light-1", "light-2"] {
+            let limit = limit.clone();
+            let granted = granted.clone();
+            actix_web::rt::spawn(async move {
+                let permit = limit.acquire(user, Duration::from_secs(5)).await.unwrap();
+                granted.send(user).unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(permit);
+            });
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(holder);
+
+        let mut order = Vec::new();
+        for _ in 0..6 {
+            order.push(grants.recv().await.unwrap());
+        }
+        assert_eq!(
+            order,
+            ["heavy", "light-1", "light-2", "heavy", "heavy", "heavy"]
+        );
+    }
+}
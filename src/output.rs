@@ -0,0 +1,1118 @@
+use async_stream::try_stream;
+use bytes::{Bytes, BytesMut};
+use chrono::{Local, SecondsFormat};
+use futures::{future, pin_mut, Stream, StreamExt, TryStreamExt};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, LineWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::{BlankLines, Options},
+    constants::{VIEWER_ENDING_BYTES, VIEWER_TEMPLATE_BYTES, VIEWER_TEMPLATE_STR},
+};
+
+// splits a raw byte stream into logical lines, holding back partial lines
+// until their newline arrives (or the stream ends). with normalize_newlines,
+// the \r of CRLF line endings is dropped as well
+fn lines<S>(stream: S, normalize_newlines: bool) -> impl Stream<Item = io::Result<String>>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    try_stream! {
+        pin_mut!(stream);
+        let mut buffer = BytesMut::new();
+
+        while let Some(chunk) = stream.next().await {
+            buffer.extend_from_slice(&chunk?);
+
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line = buffer.split_to(pos + 1);
+                yield decode_line(&line[..pos], normalize_newlines);
+            }
+        }
+
+        if !buffer.is_empty() {
+            yield decode_line(&buffer, normalize_newlines);
+        }
+    }
+}
+
+// the logical lines of one of the child's pipes, after applying the
+// line-level options
+pub fn line_stream<S>(stream: S, options: &Options) -> impl Stream<Item = io::Result<String>>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    let blank_lines = options.collapse_blank_lines;
+    let mut previous_blank = false;
+    let redact = options.redact.clone();
+    let tab_width = options.expand_tabs;
+    let collapse_carriage_returns = options.collapse_carriage_returns;
+
+    lines(stream, options.normalize_newlines)
+        .map_ok(move |line| {
+            if collapse_carriage_returns && line.contains('\r') {
+                collapse_carriage_returns_in(&line)
+            } else {
+                line
+            }
+        })
+        .try_filter(move |line| {
+            let blank = line.trim().is_empty();
+            let keep = match blank_lines {
+                BlankLines::Off => true,
+                BlankLines::Collapse => !(blank && previous_blank),
+                BlankLines::Strip => !blank,
+            };
+            previous_blank = blank;
+            future::ready(keep)
+        })
+        // secrets are masked before the line goes anywhere else
+        .map_ok(move |line| {
+            redact.iter().fold(line, |line, pattern| {
+                pattern.replace_all(&line, "***").into_owned()
+            })
+        })
+        .map_ok(move |line| match tab_width {
+            Some(width) if line.contains('\t') => expand_tabs(&line, width),
+            _ => line,
+        })
+}
+
+// what a terminal would end up showing for a line redrawn with carriage
+// returns, like a progress bar: each \r moves back to the start and the
+// text after it overwrites what was there. an erase-line sequence (ESC [K)
+// clears everything from the cursor on
+fn collapse_carriage_returns_in(line: &str) -> String {
+    let mut shown: Vec<char> = Vec::with_capacity(line.len());
+    let mut cursor = 0;
+    let mut rest = line;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest
+            .strip_prefix("\x1b[K")
+            .or_else(|| rest.strip_prefix("\x1b[0K"))
+        {
+            shown.truncate(cursor);
+            rest = after;
+            continue;
+        }
+
+        match c {
+            '\r' => cursor = 0,
+            _ if cursor < shown.len() => {
+                shown[cursor] = c;
+                cursor += 1;
+            }
+            _ => {
+                shown.push(c);
+                cursor += 1;
+            }
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    shown.into_iter().collect()
+}
+
+// replaces tabs with spaces up to the next multiple of width, so columns
+// line up the same way in every browser
+fn expand_tabs(line: &str, width: usize) -> String {
+    let width = width.max(1);
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = width - column % width;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+
+    expanded
+}
+
+// the percentage carried by a progress marker line like "BARN_PROGRESS: 42",
+// if options.progress_marker is set and the line is one
+pub fn progress(line: &str, options: &Options) -> Option<f32> {
+    let marker = options.progress_marker.as_ref()?;
+    let percent = line
+        .strip_prefix(marker.as_str())?
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f32>()
+        .ok()?;
+
+    Some(percent.clamp(0.0, 100.0))
+}
+
+// whether the line carries options.abort_on_marker, the executable's way
+// of saying it has failed without exiting
+pub fn is_abort_marker(line: &str, options: &Options) -> bool {
+    options
+        .abort_on_marker
+        .as_ref()
+        .is_some_and(|marker| line.contains(marker.as_str()))
+}
+
+fn decode_line(line: &[u8], normalize_newlines: bool) -> String {
+    let line = if normalize_newlines {
+        line.strip_suffix(b"\r").unwrap_or(line)
+    } else {
+        line
+    };
+    String::from_utf8_lossy(line).into_owned()
+}
+
+// holds on to the rendered output and only sends its end, at most
+// max_lines lines or max_bytes bytes of it, once the output is complete.
+// heartbeats still go out straight away so the connection stays open
+pub fn keep_tail<S>(
+    stream: S,
+    renderer: Renderer,
+    max_lines: Option<usize>,
+    max_bytes: Option<usize>,
+) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    let max_lines = max_lines.unwrap_or(usize::MAX);
+    let max_bytes = max_bytes.unwrap_or(usize::MAX);
+
+    let heartbeat = renderer.heartbeat();
+    // an sse event spans several lines, cutting one up would garble it
+    let separator: &[u8] = match renderer {
+        Renderer::Sse => b"\n\n",
+        _ => b"\n",
+    };
+
+    try_stream! {
+        pin_mut!(stream);
+        let mut tail = VecDeque::new();
+        let mut partial = BytesMut::new();
+        let mut bytes = 0;
+        let mut truncated = false;
+
+        loop {
+            let chunk = stream.next().await.transpose()?;
+            if let Some(beat) = chunk.as_ref().filter(|chunk| heartbeat.as_ref() == Some(chunk)) {
+                yield beat.clone();
+                continue;
+            }
+
+            // chunks don't line up with lines, so they're split up again.
+            // whatever follows the last separator waits for the next chunk
+            let mut lines = Vec::new();
+            match &chunk {
+                Some(chunk) => partial.extend_from_slice(chunk),
+                None if !partial.is_empty() => lines.push(partial.split().freeze()),
+                None => {}
+            }
+            while let Some(end) = partial
+                .windows(separator.len())
+                .position(|window| window == separator)
+            {
+                lines.push(partial.split_to(end + separator.len()).freeze());
+            }
+
+            for line in lines {
+                bytes += line.len();
+                tail.push_back(line);
+                while tail.len() > max_lines || bytes > max_bytes {
+                    if let Some(dropped) = tail.pop_front() {
+                        bytes -= dropped.len();
+                        truncated = true;
+                    }
+                }
+            }
+
+            if chunk.is_none() {
+                break;
+            }
+        }
+
+        if truncated {
+            yield renderer.warning("… earlier output truncated …");
+        }
+
+        for chunk in tail {
+            yield chunk;
+        }
+    }
+}
+
+// merges the chunks produced within window of each other's start into one,
+// so a burst of lines goes out in a single write. nothing is held back for
+// longer than the window
+pub fn coalesce<S>(stream: S, window: Duration) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    try_stream! {
+        pin_mut!(stream);
+
+        while let Some(first) = stream.next().await {
+            let mut merged = BytesMut::from(&first?[..]);
+            let deadline = tokio::time::Instant::now() + window;
+            let mut failure = None;
+
+            while let Ok(Some(chunk)) = tokio::time::timeout_at(deadline, stream.next()).await {
+                match chunk {
+                    Ok(chunk) => merged.extend_from_slice(&chunk),
+                    Err(e) => {
+                        failure = Some(e);
+                        break;
+                    }
+                }
+            }
+
+            yield merged.freeze();
+            if let Some(e) = failure {
+                Err(e)?;
+            }
+        }
+    }
+}
+
+// how much output a run has produced, both as printed by the child and as
+// rendered for the client, which for html is quite a bit more
+#[derive(Clone, Default)]
+pub struct OutputBytes {
+    raw: Arc<AtomicU64>,
+    rendered: Arc<AtomicU64>,
+}
+
+impl OutputBytes {
+    // returns the raw total so far
+    pub fn add_raw(&self, bytes: usize) -> u64 {
+        self.raw.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64
+    }
+
+    pub fn add_rendered(&self, bytes: usize) {
+        self.rendered.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.raw.load(Ordering::Relaxed)
+    }
+
+    pub fn rendered(&self) -> u64 {
+        self.rendered.load(Ordering::Relaxed)
+    }
+}
+
+// lines rendered so far, checked against options.max_lines
+#[derive(Clone, Default)]
+pub struct LineCount(Arc<AtomicU64>);
+
+pub enum LineCap {
+    Under,
+    // the first line past the cap, which the truncation notice replaces
+    Reached,
+    Past,
+}
+
+impl LineCount {
+    pub fn count(&self, max_lines: Option<u64>) -> LineCap {
+        let Some(max_lines) = max_lines else {
+            return LineCap::Under;
+        };
+
+        let count = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+        if count <= max_lines {
+            LineCap::Under
+        } else if count == max_lines + 1 {
+            LineCap::Reached
+        } else {
+            LineCap::Past
+        }
+    }
+
+    pub fn truncated(&self, max_lines: Option<u64>) -> bool {
+        max_lines.is_some_and(|max_lines| self.0.load(Ordering::Relaxed) > max_lines)
+    }
+}
+
+// the last few lines of a run's output, kept around for summaries
+#[derive(Clone)]
+pub struct OutputTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl OutputTail {
+    pub fn new(capacity: usize) -> Self {
+        OutputTail {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, line: &str) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        if self.capacity > 0 {
+            lines.push_back(line.to_string());
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// which lines get rendered, per the line_include/line_exclude patterns of
+// the executable and its groups. a line has to match one of the includes
+// (if there are any) and none of the excludes
+#[derive(Clone, Default)]
+pub struct LineFilter {
+    pub include: Vec<Regex>,
+    pub exclude: Vec<Regex>,
+}
+
+impl LineFilter {
+    pub fn allows(&self, line: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(line)))
+            && !self.exclude.iter().any(|regex| regex.is_match(line))
+    }
+}
+
+// a copy of a run's output in options.output_log_dir, named after the
+// executable and the execution id, for looking into jobs after the fact.
+// it's written as a .log.part file that becomes a .log once the run is over
+#[derive(Clone)]
+pub struct OutputLog {
+    inner: Arc<Mutex<OutputLogFile>>,
+}
+
+struct OutputLogFile {
+    file: Option<LineWriter<File>>,
+    path: PathBuf,
+    written: u64,
+    max_bytes: Option<u64>,
+}
+
+impl OutputLog {
+    // the oldest finished logs make room for this one
+    pub fn create(options: &Options, dir: &Path, id: &str, executable: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        prune_output_logs(options, dir, 1)?;
+
+        let path = dir.join(format!("{}-{}.log.part", executable, id));
+        let file = File::create(&path)?;
+        Ok(OutputLog {
+            inner: Arc::new(Mutex::new(OutputLogFile {
+                file: Some(LineWriter::new(file)),
+                path,
+                written: 0,
+                max_bytes: options.output_log_max_bytes,
+            })),
+        })
+    }
+
+    // stops writing for good once the log is full or a write fails
+    pub fn push(&self, line: &str, stream: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = match stream {
+            "stderr" => format!("[stderr] {}\n", line),
+            _ => format!("{}\n", line),
+        };
+
+        inner.written += entry.len() as u64;
+        let full = inner
+            .max_bytes
+            .is_some_and(|max_bytes| inner.written > max_bytes);
+        let Some(file) = inner.file.as_mut() else {
+            return;
+        };
+
+        let result = if full {
+            file.write_all(b"[barn] output log truncated\n")
+        } else {
+            file.write_all(entry.as_bytes())
+        };
+        if full || result.is_err() {
+            inner.file = None;
+        }
+    }
+}
+
+impl Drop for OutputLogFile {
+    fn drop(&mut self) {
+        self.file.take();
+        let _ = fs::rename(&self.path, self.path.with_extension(""));
+    }
+}
+
+// keeps the finished logs in dir within options.output_log_keep,
+// options.output_log_max_age and options.output_log_max_total_bytes, with
+// room for that many more. the newest are the ones kept
+pub fn prune_output_logs(options: &Options, dir: &Path, room: usize) -> io::Result<()> {
+    let mut logs = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "log"))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect::<Vec<_>>();
+    logs.sort_by_key(|(modified, _, _)| Reverse(*modified));
+
+    let keep = options.output_log_keep.max(1).saturating_sub(room);
+    let max_age = options.output_log_max_age.map(Duration::from_secs);
+    let mut total_bytes = 0u64;
+    for (index, (modified, size, path)) in logs.into_iter().enumerate() {
+        total_bytes = total_bytes.saturating_add(size);
+        let expired =
+            max_age.is_some_and(|max_age| modified.elapsed().is_ok_and(|age| age > max_age));
+        let oversized = options
+            .output_log_max_total_bytes
+            .is_some_and(|max| total_bytes > max);
+        if index >= keep || expired || oversized {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+// logs that were still being written when a previous barn stopped
+pub fn remove_abandoned_output_logs(dir: &Path, started: SystemTime) -> io::Result<()> {
+    for entry in fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+        let abandoned = entry.path().extension().is_some_and(|ext| ext == "part")
+            && entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified < started);
+        if abandoned {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn info_text(info: &[(&str, String)]) -> String {
+    info.iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+// viewer colour schemes, styled in viewer.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Theme::Dark),
+            "light" => Some(Theme::Light),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+        }
+    }
+}
+
+// how the child's output is presented to the client
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Renderer {
+    Html,
+    Raw,
+    Jsonl,
+    // raw output for terminals, escape codes and all, with stderr and barn's
+    // own messages coloured
+    Ansi,
+    // server-sent events, one per line, named after what they carry
+    Sse,
+}
+
+const ANSI_RED: &str = "\x1b[31m";
+const ANSI_YELLOW: &str = "\x1b[33m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+impl Renderer {
+    // the renderer an Accept header asks for, the one with the highest
+    // quality among those barn has. wildcards don't count as asking
+    pub fn from_accept(accept: &str) -> Option<Renderer> {
+        let mut best: Option<(Renderer, f32)> = None;
+
+        for entry in accept.split(',') {
+            let mut parts = entry.split(';').map(str::trim);
+            let renderer = match parts
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase()
+                .as_str()
+            {
+                "text/html" => Renderer::Html,
+                "text/plain" => Renderer::Raw,
+                "application/x-ndjson" => Renderer::Jsonl,
+                "text/event-stream" => Renderer::Sse,
+                _ => continue,
+            };
+            let quality = parts
+                .find_map(|param| param.replace(' ', "").strip_prefix("q=")?.parse().ok())
+                .unwrap_or(1.0);
+
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((renderer, quality));
+            }
+        }
+
+        best.map(|(renderer, _)| renderer)
+    }
+
+    pub fn content_type(&self, executable: &str, options: &Options) -> String {
+        match self {
+            Renderer::Html => "text/html; charset=utf-8".to_string(),
+            Renderer::Raw => Path::new(executable)
+                .extension()
+                .and_then(|ext| options.mime_types.get(&*ext.to_string_lossy()))
+                .cloned()
+                .unwrap_or_else(|| "text/plain; charset=utf-8".to_string()),
+            Renderer::Jsonl => "application/x-ndjson".to_string(),
+            Renderer::Ansi => "text/plain; charset=utf-8".to_string(),
+            Renderer::Sse => "text/event-stream".to_string(),
+        }
+    }
+
+    pub fn start(&self, theme: Option<Theme>) -> Bytes {
+        match (self, theme) {
+            (Renderer::Html, Some(theme)) => Bytes::from(VIEWER_TEMPLATE_STR.replacen(
+                "<body>",
+                &format!("<body class=\"theme-{}\">", theme.name()),
+                1,
+            )),
+            (Renderer::Html, None) => VIEWER_TEMPLATE_BYTES.clone(),
+            (Renderer::Raw | Renderer::Jsonl | Renderer::Ansi | Renderer::Sse, _) => Bytes::new(),
+        }
+    }
+
+    pub fn end(&self) -> Bytes {
+        match self {
+            Renderer::Html => VIEWER_ENDING_BYTES.clone(),
+            Renderer::Raw | Renderer::Jsonl | Renderer::Ansi | Renderer::Sse => Bytes::new(),
+        }
+    }
+
+    pub fn line(&self, line: &str, class: &str, options: &Options) -> Bytes {
+        let timestamp = options.timestamp_lines.then(|| {
+            let now = Local::now();
+            match &options.timestamp_format {
+                Some(format) => now.format(format).to_string(),
+                None => now.to_rfc3339_opts(SecondsFormat::Millis, false),
+            }
+        });
+
+        match self {
+            Renderer::Html => {
+                let timestamp = timestamp
+                    .map(|ts| format!("<span class=\"timestamp\">{}</span> ", escape_html(&ts)))
+                    .unwrap_or_default();
+                Bytes::from(format!(
+                    "<pre class=\"{}\">{}{}</pre>\n",
+                    class, timestamp, line
+                ))
+            }
+            Renderer::Raw => {
+                let timestamp = timestamp.map(|ts| ts + " ").unwrap_or_default();
+                Bytes::from(format!("{}{}\n", timestamp, line))
+            }
+            Renderer::Jsonl => json_line(match timestamp {
+                Some(timestamp) => json!({ "stream": class, "line": line, "timestamp": timestamp }),
+                None => json!({ "stream": class, "line": line }),
+            }),
+            Renderer::Ansi => {
+                let timestamp = timestamp.map(|ts| ts + " ").unwrap_or_default();
+                match class {
+                    "stderr" => {
+                        Bytes::from(format!("{}{}{}{}\n", timestamp, ANSI_RED, line, ANSI_RESET))
+                    }
+                    _ => Bytes::from(format!("{}{}\n", timestamp, line)),
+                }
+            }
+            Renderer::Sse => {
+                let timestamp = timestamp.map(|ts| ts + " ").unwrap_or_default();
+                sse_event(class, &format!("{}{}", timestamp, line))
+            }
+        }
+    }
+
+    pub fn warning(&self, message: &str) -> Bytes {
+        match self {
+            Renderer::Html => Bytes::from(format!("<p class=\"warning\">{}</p>\n", message)),
+            Renderer::Raw => Bytes::from(format!("[barn] {}\n", message)),
+            Renderer::Jsonl => json_line(json!({ "warning": message })),
+            Renderer::Ansi => {
+                Bytes::from(format!("{}[barn] {}{}\n", ANSI_YELLOW, message, ANSI_RESET))
+            }
+            Renderer::Sse => sse_event("warning", message),
+        }
+    }
+
+    // keeps intermediaries from dropping a quiet connection, if the format
+    // has room for a chunk clients will ignore
+    pub fn heartbeat(&self) -> Option<Bytes> {
+        match self {
+            Renderer::Html => Some(Bytes::from_static(b"<!-- keepalive -->\n")),
+            Renderer::Raw | Renderer::Ansi => None,
+            Renderer::Jsonl => Some(json_line(json!({ "heartbeat": true }))),
+            Renderer::Sse => Some(Bytes::from_static(b": keepalive\n\n")),
+        }
+    }
+
+    // a summary of the run for clients reading the stream programmatically,
+    // if the format is meant for them
+    pub fn result(
+        &self,
+        exit_code: Option<i32>,
+        duration: Duration,
+        truncated: bool,
+    ) -> Option<Bytes> {
+        match self {
+            Renderer::Html | Renderer::Raw | Renderer::Ansi => None,
+            Renderer::Jsonl => Some(json_line(json!({
+                "result": {
+                    "exit_code": exit_code,
+                    "duration_ms": duration.as_millis() as u64,
+                    "truncated": truncated,
+                }
+            }))),
+            Renderer::Sse => Some(sse_event(
+                "result",
+                &json!({
+                    "exit_code": exit_code,
+                    "duration_ms": duration.as_millis() as u64,
+                    "truncated": truncated,
+                })
+                .to_string(),
+            )),
+        }
+    }
+
+    // every update is sent, the viewer's css only shows the latest one
+    pub fn progress(&self, percent: f32) -> Bytes {
+        match self {
+            Renderer::Html => Bytes::from(format!(
+                "<progress class=\"progress\" value=\"{}\" max=\"100\"></progress>\n",
+                percent
+            )),
+            Renderer::Raw => Bytes::from(format!("[barn] progress: {}%\n", percent)),
+            Renderer::Ansi => Bytes::from(format!(
+                "{}[barn] progress: {}%{}\n",
+                ANSI_YELLOW, percent, ANSI_RESET
+            )),
+            Renderer::Jsonl => json_line(json!({ "progress": percent })),
+            Renderer::Sse => sse_event("progress", &percent.to_string()),
+        }
+    }
+
+    // shown below the output to say which groups allowed the execution
+    pub fn authorized_by(&self, groups: &[String]) -> Bytes {
+        let groups_text = groups.join(", ");
+        match self {
+            Renderer::Html => Bytes::from(format!(
+                "<p class=\"footer\">authorized by {}</p>\n",
+                escape_html(&groups_text)
+            )),
+            Renderer::Raw | Renderer::Ansi => {
+                Bytes::from(format!("[barn] authorized by {}\n", groups_text))
+            }
+            Renderer::Jsonl => json_line(json!({ "authorized_by": groups })),
+            Renderer::Sse => sse_event("authorized_by", &groups_text),
+        }
+    }
+
+    // what barn was asked to say about itself below the output
+    pub fn footer_info(&self, info: &[(&str, String)]) -> Bytes {
+        match self {
+            Renderer::Html => Bytes::from(format!(
+                "<p class=\"footer\">{}</p>\n",
+                escape_html(&info_text(info))
+            )),
+            Renderer::Raw | Renderer::Ansi => Bytes::from(format!("[barn] {}\n", info_text(info))),
+            Renderer::Jsonl => json_line(json!({
+                "barn": info
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), json!(value)))
+                    .collect::<serde_json::Map<_, _>>()
+            })),
+            Renderer::Sse => sse_event("footer", &info_text(info)),
+        }
+    }
+
+    // shown above the output to tell viewers what they are looking at
+    pub fn header(&self, description: &str) -> Bytes {
+        match self {
+            Renderer::Html => Bytes::from(format!(
+                "<p class=\"description\">{}</p>\n",
+                escape_html(description)
+            )),
+            Renderer::Raw | Renderer::Ansi => Bytes::new(),
+            Renderer::Jsonl => json_line(json!({ "description": description })),
+            Renderer::Sse => sse_event("description", description),
+        }
+    }
+
+    // reminds viewers how the executable expects to be invoked
+    pub fn args_help(&self, args_help: &str) -> Bytes {
+        match self {
+            Renderer::Html => Bytes::from(format!(
+                "<p class=\"description\">usage: {}</p>\n",
+                escape_html(args_help)
+            )),
+            Renderer::Raw | Renderer::Ansi => Bytes::new(),
+            Renderer::Jsonl => json_line(json!({ "args_help": args_help })),
+            Renderer::Sse => sse_event("args_help", args_help),
+        }
+    }
+
+    // the environment variables barn set for the child, by name only
+    pub fn env_names(&self, names: &[String]) -> Bytes {
+        match self {
+            Renderer::Html => Bytes::from(format!(
+                "<p class=\"description\">env: {}</p>\n",
+                escape_html(&if names.is_empty() {
+                    "none set".to_string()
+                } else {
+                    names.join(", ")
+                })
+            )),
+            Renderer::Raw | Renderer::Ansi => Bytes::new(),
+            Renderer::Jsonl => json_line(json!({ "env": names })),
+            Renderer::Sse => sse_event("env", &names.join(", ")),
+        }
+    }
+
+    // operator supplied text shown around the script's output
+    pub fn banner(&self, text: &str) -> Bytes {
+        let text = text.trim_end_matches(&['\r', '\n'][..]);
+        match self {
+            Renderer::Html => Bytes::from(format!(
+                "<pre class=\"banner\">{}</pre>\n",
+                escape_html(text)
+            )),
+            Renderer::Raw | Renderer::Ansi => Bytes::from(format!("{}\n", text)),
+            Renderer::Jsonl => json_line(json!({ "banner": text })),
+            Renderer::Sse => sse_event("banner", text),
+        }
+    }
+}
+
+fn json_line(value: serde_json::Value) -> Bytes {
+    Bytes::from(format!("{}\n", value))
+}
+
+// every line of the data gets its own field, clients join them back up
+fn sse_event(event: &str, data: &str) -> Bytes {
+    let mut event = format!("event: {}\n", event);
+    for line in data.split('\n') {
+        event.push_str("data: ");
+        event.push_str(line.trim_end_matches('\r'));
+        event.push('\n');
+    }
+    event.push('\n');
+    Bytes::from(event)
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRoot;
+
+    fn log_names(dir: &TestRoot) -> Vec<String> {
+        let mut names = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn the_oldest_output_logs_are_pruned() {
+        let dir = TestRoot::new();
+        let now = SystemTime::now();
+        for minutes in 0..5 {
+            let path = dir.file(&format!("run-{}.log", minutes), "output\n");
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(minutes * 60))
+                .unwrap();
+        }
+        dir.file("running.log.part", "output\n");
+
+        let options = Options {
+            output_log_keep: 3,
+            ..Default::default()
+        };
+        prune_output_logs(&options, dir.path(), 0).unwrap();
+        assert_eq!(
+            log_names(&dir),
+            ["run-0.log", "run-1.log", "run-2.log", "running.log.part"]
+        );
+
+        let options = Options {
+            output_log_max_age: Some(90),
+            ..Default::default()
+        };
+        prune_output_logs(&options, dir.path(), 0).unwrap();
+        assert_eq!(
+            log_names(&dir),
+            ["run-0.log", "run-1.log", "running.log.part"]
+        );
+
+        let options = Options {
+            output_log_max_total_bytes: Some(10),
+            ..Default::default()
+        };
+        prune_output_logs(&options, dir.path(), 0).unwrap();
+        assert_eq!(log_names(&dir), ["run-0.log", "running.log.part"]);
+
+        remove_abandoned_output_logs(dir.path(), now + Duration::from_secs(1)).unwrap();
+        assert_eq!(log_names(&dir), ["run-0.log"]);
+    }
+
+    #[test]
+    fn accept_headers_pick_renderers() {
+        for (accept, renderer) in [
+            ("text/html", Some(Renderer::Html)),
+            ("text/plain", Some(Renderer::Raw)),
+            ("application/x-ndjson", Some(Renderer::Jsonl)),
+            ("text/event-stream", Some(Renderer::Sse)),
+            ("text/html;q=0.5, text/event-stream", Some(Renderer::Sse)),
+            ("text/plain, application/x-ndjson;q=0", Some(Renderer::Raw)),
+            ("*/*", None),
+        ] {
+            assert_eq!(Renderer::from_accept(accept), renderer, "{}", accept);
+        }
+    }
+
+    #[test]
+    fn sse_events_frame_every_line() {
+        assert_eq!(
+            Renderer::Sse.line("hello", "stdout", &Options::default()),
+            "event: stdout\ndata: hello\n\n"
+        );
+        assert_eq!(
+            Renderer::Sse.banner("one\ntwo\n"),
+            "event: banner\ndata: one\ndata: two\n\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn only_the_tail_lines_are_kept() {
+        let heartbeat = Renderer::Html.heartbeat().unwrap();
+        let chunks = [
+            Bytes::from_static(b"one\ntwo\n"),
+            Bytes::from_static(b"three\nfo"),
+            heartbeat.clone(),
+            Bytes::from_static(b"ur\nfive"),
+        ]
+        .map(Ok);
+        let tail = keep_tail(futures::stream::iter(chunks), Renderer::Html, Some(2), None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            tail,
+            [
+                heartbeat,
+                Renderer::Html.warning("… earlier output truncated …"),
+                Bytes::from_static(b"four\n"),
+                Bytes::from_static(b"five"),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn the_tail_fits_in_max_bytes() {
+        let chunks = [Ok(Bytes::from_static(b"one\ntwo\nthree\n"))];
+        let tail = keep_tail(futures::stream::iter(chunks), Renderer::Raw, None, Some(10))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(tail.len(), 3);
+        assert_eq!(&tail[1..], [&b"two\n"[..], &b"three\n"[..]]);
+    }
+
+    #[test]
+    fn html_timestamps_are_escaped() {
+        let options = Options {
+            timestamp_lines: true,
+            timestamp_format: Some("<%Y>".to_string()),
+            ..Default::default()
+        };
+
+        let line = Renderer::Html.line("hi", "stdout", &options);
+        let line = String::from_utf8_lossy(&line);
+        assert!(line.contains("&lt;"), "{}", line);
+        assert!(!line.contains("<2"), "{}", line);
+
+        let line = Renderer::Raw.line("hi", "stdout", &options);
+        assert!(String::from_utf8_lossy(&line).starts_with('<'));
+    }
+
+    // the lines line_stream makes of the given output, as it arrives in one chunk
+    async fn lines_of(output: &'static [u8], options: &Options) -> Vec<String> {
+        let chunks = futures::stream::iter([Ok(Bytes::from_static(output))]);
+        line_stream(chunks, options).try_collect().await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn blank_lines_are_collapsed_or_stripped() {
+        let output = b"one\n\n\n  \ntwo\n\nthree\n";
+        let options = |blank_lines| Options {
+            collapse_blank_lines: blank_lines,
+            ..Options::default()
+        };
+
+        assert_eq!(
+            lines_of(output, &options(BlankLines::Off)).await,
+            ["one", "", "", "  ", "two", "", "three"]
+        );
+        assert_eq!(
+            lines_of(output, &options(BlankLines::Collapse)).await,
+            ["one", "", "two", "", "three"]
+        );
+        assert_eq!(
+            lines_of(output, &options(BlankLines::Strip)).await,
+            ["one", "two", "three"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn secrets_are_redacted() {
+        let options = Options {
+            redact: vec![
+                regex::Regex::new(r"ghp_[A-Za-z0-9]+").unwrap(),
+                regex::Regex::new(r"password=\S+").unwrap(),
+            ],
+            ..Options::default()
+        };
+
+        assert_eq!(
+            lines_of(
+                b"token ghp_abc123XYZ in use\nlogin password=hunter2 ok\nclean\n",
+                &options
+            )
+            .await,
+            ["token *** in use", "login *** ok", "clean"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn tabs_are_expanded_to_the_configured_width() {
+        let output = b"name\tsize\nbackup.tar\t12M\n\tindented\n";
+
+        assert_eq!(
+            lines_of(output, &Options::default()).await,
+            ["name\tsize", "backup.tar\t12M", "\tindented"]
+        );
+        let options = Options {
+            expand_tabs: Some(4),
+            ..Options::default()
+        };
+        assert_eq!(
+            lines_of(output, &options).await,
+            ["name    size", "backup.tar  12M", "    indented"]
+        );
+    }
+
+    #[test]
+    fn output_logs_keep_each_runs_output() {
+        let dir = TestRoot::new();
+        let options = Options {
+            output_log_max_bytes: Some(24),
+            ..Options::default()
+        };
+
+        let log = OutputLog::create(&options, dir.path(), "1234", "deploy.sh").unwrap();
+        log.push("building", "stdout");
+        log.push("warning: cache cold", "stderr");
+        log.push("never written", "stdout");
+        assert_eq!(log_names(&dir), ["deploy.sh-1234.log.part"]);
+        drop(log);
+
+        assert_eq!(log_names(&dir), ["deploy.sh-1234.log"]);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("deploy.sh-1234.log")).unwrap(),
+            "building\n[barn] output log truncated\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn carriage_returns_redraw_the_line() {
+        let output = b"fetching  10%\rfetching  55%\rfetching 100%\ndone\rok\x1b[K\n";
+        let options = Options {
+            collapse_carriage_returns: true,
+            ..Options::default()
+        };
+
+        assert_eq!(lines_of(output, &options).await, ["fetching 100%", "ok"]);
+    }
+
+    #[actix_web::test]
+    async fn bursts_of_output_are_coalesced() {
+        let burst = ["one\n", "two\n", "three\n"].map(|line| Ok(Bytes::from(line)));
+        let later = futures::stream::once(async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok(Bytes::from("four\n"))
+        });
+        let chunks = futures::stream::iter(burst).chain(later);
+
+        let coalesced: Vec<Bytes> = coalesce(chunks, Duration::from_millis(50))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(coalesced, ["one\ntwo\nthree\n", "four\n"]);
+    }
+}
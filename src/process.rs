@@ -0,0 +1,797 @@
+use async_stream::try_stream;
+use bytes::Bytes;
+use colored::Colorize;
+use futures::{future, pin_mut, Stream, StreamExt};
+use std::{
+    io,
+    path::Path,
+    process::{ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    process::{Child, Command},
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, Notify,
+    },
+    time::{sleep, timeout, timeout_at},
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+// rendered chunks buffered for a slow client before backpressure kicks in
+const BACKPRESSURE_BUFFER: usize = 256;
+
+// how long a warmup command gets before it is given up on
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+use crate::{
+    config::Backpressure,
+    limits::{ExecutionPermit, LockGuard},
+    output::{OutputBytes, OutputTail, Renderer},
+    signals::Registration,
+    webhook::{self, RunSummary, Webhook},
+};
+
+// everything about a single run that outlives the request handler
+pub struct Execution {
+    pub id: String,
+    pub executable: String,
+    pub user: Option<String>,
+    pub started: Instant,
+    pub renderer: Renderer,
+    pub idle_timeout: Option<Duration>,
+    pub max_duration: Option<Duration>,
+    pub heartbeat: Option<Duration>,
+    pub kill_grace: Option<Duration>,
+    pub post_exec: Option<PostExec>,
+    pub webhook: Option<(Webhook, OutputTail)>,
+    pub kill_switch: Arc<KillSwitch>,
+    pub permits: Vec<ExecutionPermit>,
+    pub lock: Option<LockGuard>,
+    pub exit_code: Option<oneshot::Sender<Option<i32>>>,
+    pub output_bytes: OutputBytes,
+    pub signal_registration: Option<Registration>,
+}
+
+// lets other parts of barn ask for a running child to be killed
+#[derive(Default)]
+pub struct KillSwitch {
+    notify: Notify,
+    reason: Mutex<Option<String>>,
+    timed_out: AtomicBool,
+}
+
+impl KillSwitch {
+    pub fn trigger(&self, reason: &str) {
+        let mut current = self.reason.lock().unwrap();
+        if current.is_none() {
+            *current = Some(reason.to_string());
+            self.notify.notify_one();
+        }
+    }
+
+    // like trigger, but remembers that the run went over options.max_duration
+    fn time_out(&self, duration: Duration) -> String {
+        let reason = format!("killed after running for {} seconds", duration.as_secs());
+        self.timed_out.store(true, Ordering::Relaxed);
+        self.trigger(&reason);
+        reason
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Relaxed)
+    }
+
+    async fn triggered(&self) -> String {
+        loop {
+            let reason = self.reason.lock().unwrap().clone();
+            if let Some(reason) = reason {
+                return reason;
+            }
+
+            self.notify.notified().await;
+        }
+    }
+}
+
+// owns a running child together with what has to be held until it exits.
+// if the stream is dropped early, like when the client disconnects, the
+// child is killed and reaped in the background before anything is released,
+// so a queued run never starts alongside the one it was waiting on
+struct Reaper {
+    child: Option<Child>,
+    permits: Vec<ExecutionPermit>,
+    lock: Option<LockGuard>,
+    signal_registration: Option<Registration>,
+}
+
+impl Reaper {
+    fn child(&mut self) -> &mut Child {
+        self.child
+            .as_mut()
+            .expect("the child has already been reaped")
+    }
+
+    // the registration goes first, so a signal can't reach a recycled pid
+    fn unregister(&mut self) {
+        self.signal_registration.take();
+    }
+
+    async fn wait(mut self) -> io::Result<ExitStatus> {
+        self.unregister();
+        let status = self.child().wait().await?;
+        self.child = None;
+        Ok(status)
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        self.unregister();
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+
+        let permits = std::mem::take(&mut self.permits);
+        let lock = self.lock.take();
+        actix_web::rt::spawn(async move {
+            let _ = child.kill().await;
+            drop(permits);
+            drop(lock);
+        });
+    }
+}
+
+pub struct PostExec {
+    pub command: String,
+    pub timeout: Duration,
+}
+
+// forwards the child's rendered output, killing the child if it stays
+// silent for longer than the configured idle timeout, runs for longer than
+// the maximum duration or the kill switch is triggered. with a kill grace,
+// the child gets a SIGTERM and that long to exit before being killed. the
+// concurrency permits and the named lock (if any) are held until the child
+// has been reaped
+pub fn supervise<S>(
+    child: Child,
+    output: S,
+    execution: Execution,
+) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    let Execution {
+        id,
+        executable,
+        user,
+        started,
+        renderer,
+        idle_timeout,
+        max_duration,
+        heartbeat,
+        kill_grace,
+        post_exec,
+        webhook,
+        kill_switch,
+        permits,
+        lock,
+        exit_code,
+        output_bytes,
+        signal_registration,
+    } = execution;
+    let mut reaper = Reaper {
+        child: Some(child),
+        permits,
+        lock,
+        signal_registration,
+    };
+
+    // renderers that can't carry a harmless keep-alive chunk go without
+    let heartbeat = heartbeat.zip(renderer.heartbeat());
+
+    try_stream! {
+        pin_mut!(output);
+        let mut last_output = tokio::time::Instant::now();
+        let deadline = max_duration.map(|duration| last_output + duration);
+
+        loop {
+            let next_chunk = async {
+                match idle_timeout {
+                    Some(duration) => timeout_at(last_output + duration, output.next())
+                        .await
+                        .map_err(|_| {
+                            format!("killed after {} seconds without output", duration.as_secs())
+                        }),
+                    None => Ok(output.next().await),
+                }
+            };
+            let heartbeat_due = async {
+                match &heartbeat {
+                    Some((interval, _)) => sleep(*interval).await,
+                    None => future::pending().await,
+                }
+            };
+
+            let deadline_due = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => future::pending().await,
+                }
+            };
+
+            // None when the child has been quiet for a heartbeat interval
+            let next = tokio::select! {
+                next = next_chunk => Some(next),
+                reason = kill_switch.triggered() => Some(Err(reason)),
+                _ = deadline_due => Some(Err(kill_switch.time_out(max_duration.unwrap_or_default()))),
+                _ = heartbeat_due => None,
+            };
+
+            match next {
+                None => {
+                    if let Some((_, chunk)) = &heartbeat {
+                        yield chunk.clone();
+                    }
+                }
+                Some(Ok(Some(chunk))) => {
+                    let chunk = chunk?;
+                    last_output = tokio::time::Instant::now();
+                    output_bytes.add_rendered(chunk.len());
+                    yield chunk;
+                }
+                Some(Ok(None)) => break,
+                Some(Err(reason)) => {
+                    // the child is stopped before the warning goes out, as the
+                    // client may stop reading once it has seen it
+                    reaper.unregister();
+                    let child = reaper.child();
+                    let mut cleanup_output = Vec::new();
+                    match kill_grace {
+                        Some(grace) if signal_terminate(child) => {
+                            let deadline = tokio::time::Instant::now() + grace;
+
+                            // keep whatever the child prints while it cleans up
+                            while let Ok(Some(chunk)) = timeout_at(deadline, output.next()).await {
+                                cleanup_output.push(chunk?);
+                            }
+
+                            if timeout_at(deadline, child.wait()).await.is_err() {
+                                child.kill().await?;
+                            }
+                        }
+                        _ => child.kill().await?,
+                    }
+
+                    yield renderer.warning(&reason);
+                    for chunk in cleanup_output {
+                        yield chunk;
+                    }
+                    break;
+                }
+            }
+        }
+
+        let status = reaper.wait().await?;
+
+        if let Some(exit_code) = exit_code {
+            let _ = exit_code.send(status.code());
+        }
+
+        if let Some((webhook, output_tail)) = webhook {
+            let summary = RunSummary {
+                execution_id: id.clone(),
+                executable: executable.clone(),
+                user,
+                exit_code: status.code(),
+                duration_ms: started.elapsed().as_millis(),
+                output_tail: output_tail.lines(),
+                output_bytes: output_bytes.raw(),
+                rendered_bytes: output_bytes.rendered(),
+            };
+            webhook::send(webhook, summary);
+        }
+
+        if let Some(post_exec) = post_exec {
+            let exit_code = status.code().map(|code| code.to_string()).unwrap_or_default();
+            run_post_exec(post_exec, id, executable, exit_code);
+        }
+    }
+}
+
+// prints exactly what is about to be run
+pub fn log_command(command: &Command) {
+    println!("{} {}", "[exec]".bold().blue(), describe_command(command));
+}
+
+// the argv, working directory and environment of the command. only the names
+// of the variables set for the child are given, their values may well be
+// secrets
+fn describe_command(command: &Command) -> String {
+    let command = command.as_std();
+    let argv = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>();
+    let working_dir = command
+        .get_current_dir()
+        .map(|dir| dir.to_path_buf())
+        .or_else(|| std::env::current_dir().ok())
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let env = command
+        .get_envs()
+        .map(|(name, _)| name.to_string_lossy())
+        .collect::<Vec<_>>();
+
+    format!(
+        "{:?} in '{}' (env: {})",
+        argv,
+        working_dir,
+        if env.is_empty() {
+            "inherited".to_string()
+        } else {
+            env.join(", ")
+        }
+    )
+}
+
+// a command that runs the executable confined to the chroot, by its path
+// relative to the new root. chrooting needs privileges barn may not have,
+// in which case spawning fails
+#[cfg(unix)]
+pub fn chroot_command(chroot: &Path, program_path: &Path) -> io::Result<Command> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let chroot = chroot.canonicalize()?;
+    let inner = program_path
+        .canonicalize()?
+        .strip_prefix(&chroot)
+        .map(|inner| Path::new("/").join(inner))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "outside of the chroot"))?;
+    let chroot = CString::new(chroot.as_os_str().as_bytes())?;
+
+    let mut command = Command::new(inner);
+    unsafe {
+        command.pre_exec(move || {
+            if libc::chroot(chroot.as_ptr()) != 0 || libc::chdir(c"/".as_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(command)
+}
+
+#[cfg(not(unix))]
+pub fn chroot_command(_chroot: &Path, _program_path: &Path) -> io::Result<Command> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "chroot is only supported on unix",
+    ))
+}
+
+// asks the child to exit with SIGTERM, false if it couldn't be signalled
+#[cfg(unix)]
+fn signal_terminate(child: &Child) -> bool {
+    match child.id() {
+        Some(pid) => unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) == 0 },
+        None => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn signal_terminate(_child: &Child) -> bool {
+    false
+}
+
+// what the authz hook is told about a request
+pub struct AuthzRequest {
+    pub user: String,
+    pub executable: String,
+    pub client_ip: String,
+    pub query: String,
+}
+
+// asks options.authz_hook whether the request may go ahead. anything other
+// than a zero exit within the timeout is a denial
+pub async fn run_authz_hook(
+    hook: &str,
+    hook_timeout: Duration,
+    request: &AuthzRequest,
+) -> Result<(), String> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("BARN_USER", &request.user)
+        .env("BARN_EXECUTABLE", &request.executable)
+        .env("BARN_CLIENT_IP", &request.client_ip)
+        .env("BARN_QUERY", &request.query)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status();
+
+    match timeout(hook_timeout, status).await {
+        Ok(Ok(status)) if status.success() => Ok(()),
+        Ok(Ok(status)) => Err(format!("exited with {}", status)),
+        Ok(Err(e)) => Err(format!("could not be spawned: {}", e)),
+        Err(_) => Err(format!(
+            "timed out after {} seconds",
+            hook_timeout.as_secs()
+        )),
+    }
+}
+
+// runs the post-exec hook in the background; the response has already
+// been sent by now, so failures are only logged
+fn run_post_exec(post_exec: PostExec, id: String, executable: String, exit_code: String) {
+    actix_web::rt::spawn(async move {
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&post_exec.command)
+            .env("BARN_EXECUTION_ID", &id)
+            .env("BARN_EXECUTABLE", &executable)
+            .env("BARN_EXIT_CODE", &exit_code)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status();
+
+        let failure = match timeout(post_exec.timeout, status).await {
+            Ok(Ok(status)) if status.success() => return,
+            Ok(Ok(status)) => format!("exited with {}", status),
+            Ok(Err(e)) => format!("could not be spawned: {}", e),
+            Err(_) => format!("timed out after {} seconds", post_exec.timeout.as_secs()),
+        };
+
+        println!(
+            "{} post-exec command for '{}' ({}) {}",
+            "[warn]".bold().yellow(),
+            executable,
+            id,
+            failure
+        );
+    });
+}
+
+// runs each of options.warmup once in the background so the interpreters
+// they start are already cached when the first request comes in. barn
+// starts serving regardless of how they go
+pub fn run_warmup(commands: Vec<String>) {
+    for command in commands {
+        actix_web::rt::spawn(async move {
+            let started = Instant::now();
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .kill_on_drop(true)
+                .status();
+
+            let failure = match timeout(WARMUP_TIMEOUT, status).await {
+                Ok(Ok(status)) if status.success() => {
+                    println!(
+                        "{} '{}' took {}ms",
+                        "[warmup]".bold().blue(),
+                        command,
+                        started.elapsed().as_millis()
+                    );
+                    return;
+                }
+                Ok(Ok(status)) => format!("exited with {}", status),
+                Ok(Err(e)) => format!("could not be spawned: {}", e),
+                Err(_) => format!("timed out after {} seconds", WARMUP_TIMEOUT.as_secs()),
+            };
+
+            println!(
+                "{} warmup command '{}' {}",
+                "[warn]".bold().yellow(),
+                command,
+                failure
+            );
+        });
+    }
+}
+
+// decouples the child from a slow client. rather than letting the child
+// block on a full pipe, output that doesn't fit the buffer is dropped or
+// the child is killed
+pub fn relieve_backpressure<S>(
+    output: S,
+    mode: Backpressure,
+    renderer: Renderer,
+    kill_switch: Arc<KillSwitch>,
+) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>> + 'static,
+{
+    let (tx, rx) = mpsc::channel(BACKPRESSURE_BUFFER);
+    let dropped_notice = move |dropped: usize| {
+        Ok(renderer.warning(&format!(
+            "{} lines of output were dropped because the client could not keep up",
+            dropped
+        )))
+    };
+
+    actix_web::rt::spawn(async move {
+        pin_mut!(output);
+        let mut dropped = 0;
+
+        while let Some(chunk) = output.next().await {
+            if dropped > 0 && tx.try_send(dropped_notice(dropped)).is_ok() {
+                dropped = 0;
+            }
+
+            match tx.try_send(chunk) {
+                Ok(()) => {}
+                Err(TrySendError::Full(chunk)) => match mode {
+                    Backpressure::Drop => dropped += 1,
+                    Backpressure::Kill => {
+                        kill_switch.trigger("killed because the client could not keep up");
+                        if tx.send(chunk).await.is_err() {
+                            return;
+                        }
+                    }
+                    Backpressure::Block => {
+                        if tx.send(chunk).await.is_err() {
+                            return;
+                        }
+                    }
+                },
+                Err(TrySendError::Closed(_)) => return,
+            }
+        }
+
+        if dropped > 0 {
+            let _ = tx.send(dropped_notice(dropped)).await;
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::test::{call_service, TestRequest};
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::testing::{app, basic_auth, body_text, TestRoot, USER};
+
+    // polls for a file something running in the background is going to write
+    async fn wait_for_file(path: &std::path::Path) -> String {
+        for _ in 0..50 {
+            if let Ok(contents) = std::fs::read_to_string(path) {
+                if !contents.is_empty() {
+                    return contents;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("{} was never written", path.display());
+    }
+
+    #[actix_web::test]
+    async fn post_exec_commands_are_told_about_the_run() {
+        let root = TestRoot::new();
+        root.script("fail.sh", "echo failing; exit 3");
+        let post = root.path().join("post");
+        let config = root.config(&format!(
+            "[options]\npost_exec = {:?}\n{}",
+            format!(
+                "echo \"$BARN_EXECUTABLE $BARN_EXIT_CODE ${{#BARN_EXECUTION_ID}}\" > {}",
+                post.display()
+            ),
+            USER
+        ));
+        let app = app(config).await;
+
+        let request = TestRequest::get()
+            .uri("/fail.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        body_text(call_service(&app, request).await).await;
+        assert_eq!(wait_for_file(&post).await, "fail.sh 3 32\n");
+    }
+
+    // more output than the buffer holds, read only once all of it is out
+    async fn read_slowly(mode: Backpressure, kill_switch: Arc<KillSwitch>) -> Vec<Bytes> {
+        let lines = (0..BACKPRESSURE_BUFFER + 50).map(|i| Ok(Bytes::from(format!("{}\n", i))));
+        let relieved = relieve_backpressure(
+            futures::stream::iter(lines),
+            mode,
+            Renderer::Raw,
+            kill_switch,
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        relieved.try_collect().await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn slow_clients_miss_output_in_drop_mode() {
+        let kill_switch = Arc::new(KillSwitch::default());
+        let output = read_slowly(Backpressure::Drop, kill_switch.clone()).await;
+
+        assert_eq!(output.len(), BACKPRESSURE_BUFFER + 1);
+        assert_eq!(
+            output[BACKPRESSURE_BUFFER - 1],
+            format!("{}\n", BACKPRESSURE_BUFFER - 1)
+        );
+        assert_eq!(
+            output[BACKPRESSURE_BUFFER],
+            "[barn] 50 lines of output were dropped because the client could not keep up\n"
+        );
+        assert!(kill_switch.reason.lock().unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn slow_clients_get_the_child_killed_in_kill_mode() {
+        let kill_switch = Arc::new(KillSwitch::default());
+        read_slowly(Backpressure::Kill, kill_switch.clone()).await;
+
+        assert_eq!(
+            kill_switch.reason.lock().unwrap().as_deref(),
+            Some("killed because the client could not keep up")
+        );
+    }
+
+    #[actix_web::test]
+    async fn slow_clients_hold_the_child_up_in_block_mode() {
+        let root = TestRoot::new();
+        root.script(
+            "flood.sh",
+            "seq 1 200000; echo done > \"$(dirname \"$0\")/finished\"",
+        );
+        let app = app(root.config(USER)).await;
+
+        let request = TestRequest::get()
+            .uri("/flood.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        // far more than a pipe holds, so the child can't finish until the
+        // client reads
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!root.path().join("finished").exists());
+
+        let body = body_text(response).await;
+        assert_eq!(body.lines().count(), 200000);
+        assert!(body.ends_with("199999\n200000\n"));
+        assert_eq!(wait_for_file(&root.path().join("finished")).await, "done\n");
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn timed_out_runs_get_to_clean_up() {
+        let root = TestRoot::new();
+        root.script(
+            "cleanup.sh",
+            "trap 'echo cleaning up; exit 0' TERM; echo started; while true; do sleep 0.1; done",
+        );
+        let config = root.config(&format!(
+            "[options]\nmax_duration = 1\nkill_grace = 2\n{}",
+            USER
+        ));
+        let app = app(config).await;
+
+        let request = TestRequest::get()
+            .uri("/cleanup.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let body = body_text(call_service(&app, request).await).await;
+        assert!(body.starts_with("started\n"), "{:?}", body);
+        assert!(body.contains("[barn] killed after running for 1 seconds\n"));
+        assert!(body.contains("cleaning up\n"));
+    }
+
+    #[test]
+    fn logged_commands_show_argv_but_not_env_values() {
+        let mut command = Command::new("/srv/deploy.sh");
+        command
+            .args(["--env", "prod"])
+            .current_dir("/srv")
+            .env("API_TOKEN", "hunter2");
+
+        assert_eq!(
+            describe_command(&command),
+            "[\"/srv/deploy.sh\", \"--env\", \"prod\"] in '/srv' (env: API_TOKEN)"
+        );
+    }
+
+    #[actix_web::test]
+    async fn abort_markers_kill_the_run() {
+        let root = TestRoot::new();
+        root.script(
+            "migrate.sh",
+            "echo step 1; echo 'FATAL: disk full'; sleep 1; echo step 2",
+        );
+        let config = root.config(&format!(
+            "[options]\nabort_on_marker = \"FATAL:\"\n{}",
+            USER
+        ));
+        let app = app(config).await;
+
+        let request = TestRequest::get()
+            .uri("/migrate.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let started = std::time::Instant::now();
+        let body = body_text(call_service(&app, request).await).await;
+        assert!(body.starts_with("step 1\nFATAL: disk full\n"), "{:?}", body);
+        assert!(body.contains("[barn] aborted, the executable reported a failure\n"));
+        assert!(!body.contains("step 2"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    // chrooting needs root, so elsewhere there is nothing to check
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn chrooted_children_only_see_the_chroot() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        // a shell and the libraries it loads, copied into the new root
+        let root = TestRoot::new();
+        let ldd = std::process::Command::new("ldd")
+            .arg("/bin/sh")
+            .output()
+            .unwrap();
+        let libraries = String::from_utf8_lossy(&ldd.stdout)
+            .split_whitespace()
+            .filter(|word| word.starts_with('/'))
+            .map(String::from)
+            .collect::<Vec<_>>();
+        for file in libraries.iter().map(String::as_str).chain(["/bin/sh"]) {
+            let copy = root.path().join(file.trim_start_matches('/'));
+            std::fs::create_dir_all(copy.parent().unwrap()).unwrap();
+            std::fs::copy(file, copy).unwrap();
+        }
+        root.file("inside", "");
+        root.script("list.sh", "for entry in /*; do echo \"$entry\"; done");
+        let config = root.config(&format!(
+            "[options]\nchroot = {:?}\n{}",
+            root.path().display().to_string(),
+            USER
+        ));
+        let app = app(config).await;
+
+        let request = TestRequest::get()
+            .uri("/list.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let body = body_text(call_service(&app, request).await).await;
+        assert!(body.contains("/inside\n"), "{}", body);
+        assert!(body.contains("/list.sh\n"), "{}", body);
+        assert!(!body.contains("/proc\n"), "{}", body);
+    }
+
+    #[actix_web::test]
+    async fn warmup_runs_in_the_background() {
+        let root = TestRoot::new();
+        let warm = root.path().join("warm");
+
+        // failing and slow interpreters hold nothing up
+        let started = Instant::now();
+        run_warmup(vec![
+            "exit 1".to_string(),
+            "sleep 10".to_string(),
+            format!("echo warm > {:?}", warm.display().to_string()),
+        ]);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        assert_eq!(wait_for_file(&warm).await, "warm\n");
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use colored::Colorize;
+use serde_json::json;
+
+use crate::{
+    utils::{authenticate, log_rejection, templated_error},
+    BarnState,
+};
+
+// sent back with runs that can be signalled, so the client knows which
+// execution to address
+pub const EXECUTION_ID_HEADER: &str = "x-barn-execution-id";
+
+// runs whose groups allow clients to signal them, keyed by execution id
+#[derive(Default)]
+pub struct Signalable {
+    runs: Arc<Mutex<HashMap<String, SignalableRun>>>,
+}
+
+struct SignalableRun {
+    pid: u32,
+    user: String,
+    allowed: Vec<i32>,
+}
+
+// keeps a run signalable until it's dropped, which has to happen before
+// the child is reaped
+pub struct Registration {
+    id: String,
+    runs: Arc<Mutex<HashMap<String, SignalableRun>>>,
+}
+
+pub enum SignalError {
+    // unknown runs and other users' runs look the same from outside
+    NotFound,
+    NotAllowed,
+    Failed(io::Error),
+}
+
+impl Signalable {
+    pub fn register(&self, id: &str, pid: u32, user: &str, allowed: Vec<i32>) -> Registration {
+        let run = SignalableRun {
+            pid,
+            user: user.to_string(),
+            allowed,
+        };
+        self.runs.lock().unwrap().insert(id.to_string(), run);
+
+        Registration {
+            id: id.to_string(),
+            runs: self.runs.clone(),
+        }
+    }
+
+    pub fn send(&self, id: &str, user: &str, signal: i32) -> Result<(), SignalError> {
+        let runs = self.runs.lock().unwrap();
+        let run = runs
+            .get(id)
+            .filter(|run| run.user == user)
+            .ok_or(SignalError::NotFound)?;
+        if !run.allowed.contains(&signal) {
+            return Err(SignalError::NotAllowed);
+        }
+
+        // runs are unregistered before their child is reaped, so the pid
+        // still belongs to it
+        kill(run.pid, signal).map_err(SignalError::Failed)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.runs.lock().unwrap().remove(&self.id);
+    }
+}
+
+// the number of a signal named like "SIGINT" or "INT"
+#[cfg(unix)]
+pub fn signal_number(name: &str) -> Option<i32> {
+    let name = name.to_ascii_uppercase();
+    let signal = match name.strip_prefix("SIG").unwrap_or(&name) {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        "WINCH" => libc::SIGWINCH,
+        _ => return None,
+    };
+
+    Some(signal)
+}
+
+#[cfg(not(unix))]
+pub fn signal_number(_name: &str) -> Option<i32> {
+    None
+}
+
+#[cfg(unix)]
+fn kill(pid: u32, signal: i32) -> io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill(_pid: u32, _signal: i32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "signals are only supported on unix",
+    ))
+}
+
+// lets the user who started a run send it one of the signals its groups
+// allow, like SIGINT to have a long job wrap up early
+pub async fn signal_handler(
+    req: HttpRequest,
+    params: web::Path<(String, String)>,
+    creds: Option<BasicAuth>,
+    data: web::Data<BarnState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let config = data.config();
+    let user = authenticate(&req, creds, &config)?;
+    let (id, name) = params.into_inner();
+
+    let Some(signal) = signal_number(&name) else {
+        return Err(templated_error("Unknown signal", StatusCode::BAD_REQUEST));
+    };
+
+    match data.signalable.send(&id, &user.username, signal) {
+        Ok(()) => {
+            println!(
+                "{} '{}' sent {} to execution {}",
+                "[signal]".bold().blue(),
+                user.username,
+                name,
+                id
+            );
+            Ok(HttpResponse::Ok().json(json!({ "signalled": true })))
+        }
+        Err(SignalError::NotFound) => Err(templated_error(
+            "No such execution is running",
+            StatusCode::NOT_FOUND,
+        )),
+        Err(SignalError::NotAllowed) => {
+            log_rejection(
+                &req,
+                &format!(
+                    "user '{}' denied sending {} to execution {}",
+                    user.username, name, id
+                ),
+            );
+            Err(templated_error(
+                "That signal isn't allowed for this execution",
+                StatusCode::FORBIDDEN,
+            ))
+        }
+        Err(SignalError::Failed(e)) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "signalled": false, "error": e.to_string() }))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use super::EXECUTION_ID_HEADER;
+    use crate::testing::{app, basic_auth, body_text, TestRoot};
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn runs_take_only_the_allowed_signals() {
+        let root = TestRoot::new();
+        root.script(
+            "job.sh",
+            concat!(
+                "trap 'echo interrupted; exit 0' INT; echo started; ",
+                "touch \"$(dirname \"$0\")/trapped\"; for i in $(seq 100); do sleep 0.1; done",
+            ),
+        );
+        let config = root.config(concat!(
+            "[[user]]\nusername = \"user\"\npassword = \"password\"\ngroups = [\"jobs\"]\n",
+            "[[group]]\nname = \"jobs\"\nregex = \".*\"\nsignals = [\"SIGINT\"]\n",
+        ));
+        let app = app(config).await;
+        let signal = |id: &str, name: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/executions/{}/signal/{}", id, name))
+                .insert_header(basic_auth("user", "password"))
+                .to_request()
+        };
+
+        let request = test::TestRequest::get()
+            .uri("/job.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let run = test::call_service(&app, request).await;
+        let id = run
+            .headers()
+            .get(EXECUTION_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // a signal that arrives before the trap is set would kill the shell
+        while !root.path().join("trapped").exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        let refused = test::call_service(&app, signal(&id, "TERM")).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        let sent = test::call_service(&app, signal(&id, "INT")).await;
+        assert_eq!(sent.status(), StatusCode::OK);
+
+        assert_eq!(body_text(run).await, "started\ninterrupted\n");
+        let finished = test::call_service(&app, signal(&id, "INT")).await;
+        assert_eq!(finished.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,107 @@
+// helpers for the tests that need a config, executables or the whole app
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceResponse},
+    middleware::NormalizePath,
+    test, web, App, Error,
+};
+use actix_web_httpauth::headers::authorization::{Authorization, Basic};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{config::Config, routes, BarnState};
+
+// a user who may run everything, for configs that need somebody to log in
+pub const USER: &str = r#"
+[[user]]
+username = "user"
+password = "password"
+groups = ["everyone"]
+
+[[group]]
+name = "everyone"
+regex = ".*"
+"#;
+
+// the error pages are global, so the tests that set them, directly or by
+// reloading a config, take turns
+pub static ERROR_PAGE_TESTS: Mutex<()> = Mutex::const_new(());
+
+// a scratch directory that is removed again once the test is done
+pub struct TestRoot {
+    path: PathBuf,
+}
+
+impl TestRoot {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("barn-test-{}", Uuid::new_v4().simple()));
+        fs::create_dir_all(&path).unwrap();
+        TestRoot { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.path.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    // an executable shell script running the given body
+    pub fn script(&self, name: &str, body: &str) -> PathBuf {
+        let path = self.file(name, &format!("#!/bin/sh\n{}\n", body));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    // a config parsed from toml, serving executables from this directory
+    pub fn config(&self, toml: &str) -> Config {
+        let mut config: Config = toml::from_str(toml).unwrap();
+        config.options.root = self.path.clone();
+        config
+    }
+}
+
+impl Drop for TestRoot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// the app as main serves it
+pub async fn app(
+    config: Config,
+) -> impl Service<actix_http::Request, Response = ServiceResponse<impl MessageBody>, Error = Error>
+{
+    app_with_state(web::Data::new(BarnState::new(config, None))).await
+}
+
+// for tests that need to look at the state, or give it a config file
+pub async fn app_with_state(
+    state: web::Data<BarnState>,
+) -> impl Service<actix_http::Request, Response = ServiceResponse<impl MessageBody>, Error = Error>
+{
+    test::init_service(
+        App::new()
+            .app_data(state)
+            .wrap(NormalizePath::trim())
+            .configure(routes),
+    )
+    .await
+}
+
+pub fn basic_auth(username: &str, password: &str) -> Authorization<Basic> {
+    Authorization::from(Basic::new(username.to_string(), Some(password.to_string())))
+}
+
+pub async fn body_text(response: ServiceResponse<impl MessageBody>) -> String {
+    String::from_utf8_lossy(&test::read_body(response).await).to_string()
+}
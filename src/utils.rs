@@ -1,46 +1,122 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
 
+use actix_http::Request;
 use actix_web::{
-    dev::ServiceRequest, error::InternalError, http::StatusCode, web, Error, HttpResponse,
+    dev::{ResourceDef, ServiceRequest},
+    error::InternalError,
+    http::{header::Header, StatusCode},
+    web, Error, HttpMessage, HttpRequest, HttpResponse,
+};
+use actix_web_httpauth::{
+    extractors::{basic::BasicAuth, AuthenticationError},
+    headers::{
+        authorization::{Authorization, Basic as BasicCredentials},
+        www_authenticate::basic::Basic,
+    },
 };
-use actix_web_httpauth::extractors::basic::BasicAuth;
 use anyhow::{anyhow, Context, Result};
-use bytes::Bytes;
+use chrono::{Local, Utc};
+use colored::Colorize;
+use ipnet::IpNet;
+use lazy_static::lazy_static;
+use uuid::Uuid;
 
 use crate::{
+    config::{AuthMode, Config, Options, OutputText, User},
     constants::{FILENAME_REGEX, VIEWER_TEMPLATE_STR},
+    output::escape_html,
     BarnState,
 };
 
-pub fn transform_bytes(bytes: Bytes, class: &str) -> Bytes {
-    let str = String::from_utf8(bytes.into()).unwrap();
-    let modified = str
-        .lines()
-        .map(|line| format!("<pre class=\"{}\">{}</pre>\n", class, line))
-        .collect::<Vec<_>>()
-        .join("");
-    Bytes::from(modified)
+pub fn templated_page(message: &str) -> String {
+    format!(
+        "{}{}{}{}",
+        *VIEWER_TEMPLATE_STR, "<p class=\"warning\">", message, "</p> </div> </body> </html>"
+    )
+}
+
+lazy_static! {
+    // options.error_pages of the config in use, swapped on reload
+    static ref ERROR_PAGES: RwLock<HashMap<u16, OutputText>> = RwLock::new(HashMap::new());
+}
+
+pub fn set_error_pages(error_pages: &HashMap<u16, OutputText>) {
+    *ERROR_PAGES.write().unwrap() = error_pages.clone();
+}
+
+fn error_page(message: &str, status_code: StatusCode) -> String {
+    page_for_status(&ERROR_PAGES.read().unwrap(), message, status_code)
+}
+
+// the operator's page for the status if there is one, the generic one if
+// not (or if theirs can't be read)
+fn page_for_status(
+    error_pages: &HashMap<u16, OutputText>,
+    message: &str,
+    status_code: StatusCode,
+) -> String {
+    let page = error_pages.get(&status_code.as_u16()).map(OutputText::read);
+    match page {
+        Some(Ok(page)) => page.replace("{message}", message),
+        Some(Err(e)) => {
+            println!(
+                "{} unable to read the error page for {}: {}",
+                "[warn]".bold().yellow(),
+                status_code.as_u16(),
+                e
+            );
+            templated_page(message)
+        }
+        None => templated_page(message),
+    }
 }
 
 pub fn templated_error(message: &str, status_code: StatusCode) -> Error {
+    error_response(message, status_code, None)
+}
+
+// builds the error sent to the client. server errors get a short reference
+// that is logged along with the cause, so reports can be matched to logs
+fn error_response(message: &str, status_code: StatusCode, cause: Option<String>) -> Error {
+    let message = if status_code.is_server_error() {
+        let (message, logged) = referenced_error(message, cause);
+        println!("{} {}", "[error]".bold().red(), logged);
+        message
+    } else {
+        message.to_string()
+    };
+
+    let body = error_page(&message, status_code);
     let response = HttpResponse::build(status_code)
         .content_type("text/html; charset=utf-8")
-        .body(message.to_string());
+        .body(body.clone());
 
-    let msg = format!(
-        "{}{}{}{}",
-        *VIEWER_TEMPLATE_STR, "<p class=\"warning\">", message, "</p> </div> </body> </html>"
+    InternalError::from_response(body, response).into()
+}
+
+// the message for the client and the line for the log, sharing a reference
+fn referenced_error(message: &str, cause: Option<String>) -> (String, String) {
+    let reference = Uuid::new_v4().simple().to_string()[..8].to_string();
+    let logged = format!(
+        "[{}] {}{}",
+        reference,
+        message,
+        cause
+            .map(|cause| format!(": {}", cause))
+            .unwrap_or_default()
     );
 
-    InternalError::from_response(msg, response).into()
+    (format!("{} (reference: {})", message, reference), logged)
 }
 
 pub trait IntoHttpError<T> {
-    fn http_error(
-        self,
-        message: &str,
-        status_code: StatusCode,
-    ) -> core::result::Result<T, actix_web::Error>;
+    // the value, or what went wrong instead (if known) for the server log
+    fn into_cause(self) -> core::result::Result<T, Option<String>>;
 
     fn templated_error(
         self,
@@ -50,17 +126,8 @@ pub trait IntoHttpError<T> {
     where
         Self: std::marker::Sized,
     {
-        self.http_error(
-            format!(
-                "{}{}{}{}",
-                *VIEWER_TEMPLATE_STR,
-                "<p class=\"warning\">",
-                message,
-                "</p> </div> </body> </html>"
-            )
-            .as_str(),
-            status_code,
-        )
+        self.into_cause()
+            .map_err(|cause| error_response(message, status_code, cause))
     }
 
     fn generic_error(self) -> core::result::Result<T, actix_web::Error>
@@ -72,41 +139,48 @@ pub trait IntoHttpError<T> {
 }
 
 impl<T, E: std::fmt::Debug> IntoHttpError<T> for core::result::Result<T, E> {
-    fn http_error(
-        self,
-        message: &str,
-        status_code: StatusCode,
-    ) -> core::result::Result<T, actix_web::Error> {
-        match self {
-            Ok(val) => Ok(val),
-            Err(_) => {
-                let response = HttpResponse::build(status_code)
-                    .content_type("text/html; charset=utf-8")
-                    .body(message.to_string());
-                Err(InternalError::from_response(message.to_string(), response).into())
-            }
-        }
+    fn into_cause(self) -> core::result::Result<T, Option<String>> {
+        self.map_err(|e| Some(format!("{:?}", e)))
     }
 }
 
 impl<T> IntoHttpError<T> for core::option::Option<T> {
-    fn http_error(
-        self,
-        message: &str,
-        status_code: StatusCode,
-    ) -> core::result::Result<T, actix_web::Error> {
-        match self {
-            Some(val) => Ok(val),
-            None => {
-                let response = HttpResponse::build(status_code)
-                    .content_type("text/html; charset=utf-8")
-                    .body(message.to_string());
-                Err(InternalError::from_response(message.to_string(), response).into())
-            }
-        }
+    fn into_cause(self) -> core::result::Result<T, Option<String>> {
+        self.ok_or(None)
     }
 }
 
+pub fn is_executable(path: &Path) -> bool {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o100 != 0
+    }
+
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+// names of the files in the root that barn would be willing to run
+pub fn list_executables(root: &Path) -> std::io::Result<Vec<String>> {
+    let mut executables = std::fs::read_dir(root)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| is_executable(&entry.path()))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| FILENAME_REGEX.is_match(name))
+        .collect::<Vec<_>>();
+
+    executables.sort();
+    Ok(executables)
+}
+
 pub fn check_executables_root(root: &PathBuf) -> Result<()> {
     // check if the executables root folder exists and is a dir
     if !root.exists() || !root.is_dir() {
@@ -138,83 +212,1376 @@ pub fn check_executables_root(root: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-pub async fn request_validator(
-    req: ServiceRequest,
-    creds: BasicAuth,
-) -> Result<ServiceRequest, (Error, ServiceRequest)> {
-    let config = &req.app_data::<web::Data<BarnState>>().unwrap().config;
-    let executable = req.path().trim_start_matches("/");
-    let program_path = config.options.root.join(&executable);
+// executables run inside options.chroot are looked up relative to it, so
+// the executables' root has to be somewhere inside
+pub fn check_chroot(options: &Options) -> Result<()> {
+    let Some(chroot) = &options.chroot else {
+        return Ok(());
+    };
 
-    if !FILENAME_REGEX.is_match(&executable) {
-        return Err((
-            templated_error("Disallowed filename", StatusCode::BAD_REQUEST),
-            req,
-        ));
+    if cfg!(not(unix)) {
+        Err(anyhow!("chroot is only supported on unix"))?
     }
 
-    if !program_path.exists() || !program_path.is_file() {
-        return Err((
-            templated_error("Non-existent executable", StatusCode::BAD_REQUEST),
-            req,
-        ));
+    let chroot = chroot
+        .canonicalize()
+        .with_context(|| format!("Unable to resolve the chroot '{}'", chroot.display()))?;
+    let root = options.root.canonicalize()?;
+    if !root.starts_with(&chroot) {
+        Err(anyhow!(
+            "The executables' root '{}' is outside of the chroot '{}'",
+            root.display(),
+            chroot.display()
+        ))?
     }
 
-    // if this script belongs to the 'passwordless' group, no auth should be done
-    let is_passwordless = config
-        .group
+    Ok(())
+}
+
+// the name of the machine barn is running on
+#[cfg(unix)]
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+// why the executable can't be trusted under options.require_owner and
+// options.reject_writable, if it can't. a script others can write to could
+// have been swapped out for anything
+#[cfg(unix)]
+pub fn untrusted_executable(path: &Path, options: &Options) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    if options.require_owner.is_none() && !options.reject_writable {
+        return None;
+    }
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return Some(format!("its owner can't be checked ({})", e)),
+    };
+
+    if let Some(owner) = options.require_owner {
+        if metadata.uid() != owner {
+            return Some(format!(
+                "it is owned by uid {}, not require_owner ({})",
+                metadata.uid(),
+                owner
+            ));
+        }
+    }
+
+    if options.reject_writable && metadata.mode() & 0o022 != 0 {
+        return Some("it is group or world writable".to_string());
+    }
+
+    None
+}
+
+#[cfg(not(unix))]
+pub fn untrusted_executable(_path: &Path, _options: &Options) -> Option<String> {
+    None
+}
+
+// the user that request_validator let through, for use by the handler
+pub struct AuthenticatedUser(pub String);
+
+// the groups that let the request through, for use by the handler
+pub struct AuthorizingGroups(pub Vec<String>);
+
+// the path a request for the fallback executable was originally made to
+pub struct RequestedPath(pub String);
+
+// the name of the executable a request resolved to, for use by the handler
+pub struct ResolvedExecutable(pub String);
+
+// the path prefix a request came in under with options.path_scopes, which
+// fixes how the output is rendered
+#[derive(Clone, Copy, PartialEq)]
+pub enum PathScope {
+    // /view/, always the html viewer
+    View,
+    // /api/, raw output (or jsonl if configured)
+    Api,
+}
+
+// the executable a request path refers to. with case_insensitive_paths, a
+// name that doesn't exist as is may still match a single executable when
+// ignoring case. if several match, none of them is picked
+pub fn resolve_executable(name: &str, options: &Options) -> Option<String> {
+    if !options.case_insensitive_paths || options.root.join(name).exists() {
+        return Some(name.to_string());
+    }
+
+    let mut matches = list_executables(&options.root)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|executable| executable.eq_ignore_ascii_case(name));
+
+    match (matches.next(), matches.next()) {
+        (Some(executable), None) => Some(executable),
+        (None, _) => Some(name.to_string()),
+        (Some(_), Some(_)) => None,
+    }
+}
+
+pub fn client_ip(req: &HttpRequest, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+
+    // only a trusted proxy gets to tell us who the client is
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+
+    let forwarded_for = req
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .filter_map(|entry| entry.trim().parse::<IpAddr>().ok())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    // walk the chain from the nearest hop, skipping proxies we trust
+    Some(
+        forwarded_for
+            .iter()
+            .rev()
+            .find(|ip| !is_trusted(ip))
+            .or_else(|| forwarded_for.first())
+            .copied()
+            .unwrap_or(peer),
+    )
+}
+
+// whether the client reached us over https, either directly or as told by
+// a trusted proxy through X-Forwarded-Proto
+pub fn is_https(req: &HttpRequest, trusted_proxies: &[IpNet]) -> bool {
+    let from_trusted_proxy = req
+        .peer_addr()
+        .is_some_and(|peer| trusted_proxies.iter().any(|net| net.contains(&peer.ip())));
+
+    if from_trusted_proxy {
+        if let Some(proto) = req.headers().get("X-Forwarded-Proto") {
+            return proto
+                .to_str()
+                .is_ok_and(|proto| proto.trim().eq_ignore_ascii_case("https"));
+        }
+    }
+
+    req.app_config().secure()
+}
+
+// the user named by options.trusted_user_header, honoured only when the
+// request comes straight from a trusted proxy
+fn forwarded_user(req: &HttpRequest, options: &Options) -> Option<String> {
+    let header = options.trusted_user_header.as_ref()?;
+    let peer = req.peer_addr()?.ip();
+
+    if !options
+        .trusted_proxies
         .iter()
-        .any(|entry| entry.name == "passwordless" && entry.regex.is_match(executable));
+        .any(|net| net.contains(&peer))
+    {
+        return None;
+    }
 
-    if is_passwordless {
-        return Ok(req);
+    req.headers()
+        .get(header)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+pub fn log_rejection(req: &HttpRequest, reason: &str) {
+    let config = req.app_data::<web::Data<BarnState>>().unwrap().config();
+    let ip = client_ip(req, &config.options.trusted_proxies)
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("{} {} (from {})", "[auth]".bold().yellow(), reason, ip);
+}
+
+// the configured user making the request
+pub fn authenticate<'a>(
+    req: &HttpRequest,
+    creds: Option<BasicAuth>,
+    config: &'a Config,
+) -> Result<&'a User, Error> {
+    // a trusted proxy may vouch for the user instead of us checking creds
+    if let Some(username) = forwarded_user(req, &config.options) {
+        let user_opt = config.user.iter().find(|entry| entry.username == username);
+
+        return match user_opt {
+            Some(user) => Ok(user),
+            None => {
+                log_rejection(req, &format!("unknown forwarded user '{}'", username));
+                Err(templated_error(
+                    "Invalid credentials",
+                    StatusCode::UNAUTHORIZED,
+                ))
+            }
+        };
     }
 
     // check if creds were provided and obtain them
+    let creds = match creds {
+        Some(creds) => creds,
+        None => return Err(AuthenticationError::new(Basic::default()).into()),
+    };
+
     let username = creds.user_id();
     let password_res = creds.password();
     let password = match password_res {
         Some(p) => p,
         None => {
-            return Err((
-                templated_error("No password provided", StatusCode::BAD_REQUEST),
-                req,
+            return Err(templated_error(
+                "No password provided",
+                StatusCode::BAD_REQUEST,
             ))
         }
     };
 
-    // check if a user with the given creds exists
-    let user_opt = config
+    match find_user(config, username, Some(password)) {
+        Some(user) => Ok(user),
+        None => {
+            log_rejection(req, &format!("invalid credentials for user '{}'", username));
+            Err(templated_error(
+                "Invalid credentials",
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+    }
+}
+
+// the configured user with these credentials
+fn find_user<'a>(config: &'a Config, username: &str, password: Option<&str>) -> Option<&'a User> {
+    config
         .user
         .iter()
-        .find(|entry| entry.username == username && entry.password == password);
+        .find(|entry| entry.username == username && Some(entry.password.as_str()) == password)
+}
+
+// headers that carry credentials are never written to the audit log
+const SECRET_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie"];
+
+// records who started which execution, and optionally with what request
+pub fn log_audit(
+    req: &HttpRequest,
+    execution_id: &str,
+    executable: &str,
+    user: Option<&str>,
+    options: &Options,
+) {
+    let line = audit_line(req, execution_id, executable, user, options);
+    println!("{} {}", "[audit]".bold().green(), line);
+}
+
+fn audit_line(
+    req: &HttpRequest,
+    execution_id: &str,
+    executable: &str,
+    user: Option<&str>,
+    options: &Options,
+) -> String {
+    let ip = client_ip(req, &options.trusted_proxies)
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let mut line = format!(
+        "{} '{}' ran '{}' from {}",
+        execution_id,
+        user.unwrap_or("anonymous"),
+        executable,
+        ip
+    );
+
+    if options.audit_include_query && !req.query_string().is_empty() {
+        line += &format!(" query: {}", req.query_string());
+    }
+
+    let headers = options
+        .audit_headers
+        .iter()
+        .filter(|name| !SECRET_HEADERS.contains(&name.to_ascii_lowercase().as_str()))
+        .filter_map(|name| {
+            let value = req.headers().get(name.as_str())?.to_str().ok()?;
+            Some(format!("{}={:?}", name, value))
+        })
+        .collect::<Vec<_>>();
+    if !headers.is_empty() {
+        line += &format!(" headers: {}", headers.join(", "));
+    }
+
+    if let Some(groups) = req.extensions().get::<AuthorizingGroups>() {
+        line += &format!(" groups: {}", groups.0.join(", "));
+    }
+
+    line
+}
+
+// the user's groups, or the ones every user gets, that cover the executable
+fn granting_groups(config: &Config, user: &User, executable: &str) -> Vec<String> {
+    config
+        .group
+        .iter()
+        .filter(|entry| {
+            user.groups.contains(&entry.name) || config.options.default_groups.contains(&entry.name)
+        })
+        .filter(|entry| entry.regex.is_match(executable))
+        .map(|entry| entry.name.clone())
+        .collect()
+}
+
+// the closest existing executable to a mistyped name, as long as whoever
+// made the request would be allowed to run it
+fn suggest_executable(
+    req: &HttpRequest,
+    creds: Option<&BasicAuth>,
+    config: &Config,
+    discovered: Option<&[String]>,
+    name: &str,
+) -> Option<String> {
+    // identified quietly, a wrong password shouldn't be logged twice
+    let user = forwarded_user(req, &config.options)
+        .and_then(|username| config.user.iter().find(|entry| entry.username == username))
+        .or_else(|| {
+            let creds = creds?;
+            config.user.iter().find(|entry| {
+                entry.username == creds.user_id()
+                    && Some(entry.password.as_str()) == creds.password()
+            })
+        });
+
+    let candidates = match discovered {
+        Some(names) => names.to_vec(),
+        None => list_executables(&config.options.root).ok()?,
+    };
+
+    candidates
+        .into_iter()
+        .filter(|executable| !config.is_hidden(executable))
+        .map(|executable| (edit_distance(name, &executable), executable))
+        .filter(|(distance, _)| *distance <= (name.chars().count() / 3).max(1))
+        .filter(|(_, executable)| can_run(config, user, executable))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, executable)| executable)
+}
+
+// whether the user, or anyone at all when there's no user, may run the
+// executable as far as credentials go
+pub fn can_run(config: &Config, user: Option<&User>, executable: &str) -> bool {
+    config.options.auth == AuthMode::None
+        || config
+            .group
+            .iter()
+            .any(|entry| entry.name == "passwordless" && entry.regex.is_match(executable))
+        || user.is_some_and(|user| !granting_groups(config, user, executable).is_empty())
+}
+
+// levenshtein distance between two names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+// the requested path without the leading slash and, with options.path_scopes,
+// without the scope's prefix
+fn unscoped_path(path: &str, options: &Options) -> (String, Option<PathScope>) {
+    let path = path.trim_start_matches('/');
+    if options.path_scopes {
+        let scoped = [("view/", PathScope::View), ("api/", PathScope::Api)]
+            .into_iter()
+            .find_map(|(prefix, scope)| Some((path.strip_prefix(prefix)?, scope)));
+        if let Some((unscoped, scope)) = scoped {
+            return (unscoped.to_string(), Some(scope));
+        }
+    }
+
+    (path.to_string(), None)
+}
+
+// barn being down for maintenance is planned, so it isn't reported as a
+// server error
+fn maintenance_error() -> Error {
+    let body = templated_page("Barn is down for maintenance, please try again later");
+    let response = HttpResponse::ServiceUnavailable()
+        .content_type("text/html; charset=utf-8")
+        .body(body.clone());
+    InternalError::from_response(body, response).into()
+}
+
+// clients that send Expect: 100-continue hold their body back until they're
+// told to go ahead. whatever can be told from the headers alone is checked
+// first, so an upload that would be refused is never sent. request_validator
+// still runs once the request is let through
+pub async fn continue_validator(
+    req: Request,
+    state: web::Data<BarnState>,
+) -> Result<Request, Error> {
+    let config = state.config();
+
+    // the path as the router sees it once NormalizePath::trim() is done
+    // with it. the routes that don't run anything are left alone
+    let segments = req.path().split('/').filter(|segment| !segment.is_empty());
+    let path = format!("/{}", segments.collect::<Vec<_>>().join("/"));
+    let other_routes = ResourceDef::new(vec![
+        "/",
+        "/admin/reload",
+        "/executions/{id}/signal/{signal}",
+    ]);
+    if other_routes.is_match(&path) {
+        return Ok(req);
+    }
+
+    if config.options.maintenance {
+        return Err(maintenance_error());
+    }
+
+    // with a fallback, any path may end up running something
+    if config.options.fallback_executable.is_some() {
+        return Ok(req);
+    }
+
+    let (path, _) = unscoped_path(&path, &config.options);
+    let executable = config.route(&path).unwrap_or(&path);
+    if !FILENAME_REGEX.is_match(executable) {
+        return Err(templated_error(
+            "Disallowed filename",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let Some(executable) = resolve_executable(executable, &config.options) else {
+        return Ok(req);
+    };
+
+    // requests that may get in without credentials are left to the full check
+    let credentials_optional = config.options.auth == AuthMode::None
+        || config.options.trusted_user_header.is_some()
+        || config
+            .group
+            .iter()
+            .filter(|entry| entry.regex.is_match(&executable))
+            .any(|entry| entry.name == "passwordless" || !entry.trusted_ips.is_empty())
+        || config
+            .executable(&executable)
+            .is_some_and(|entry| !entry.trusted_ips.is_empty());
+    if credentials_optional {
+        return Ok(req);
+    }
+
+    let Ok(creds) = Authorization::<BasicCredentials>::parse(&req) else {
+        return Err(AuthenticationError::new(Basic::default()).into());
+    };
+    let creds = creds.into_scheme();
+    let Some(user) = find_user(&config, creds.user_id(), creds.password()) else {
+        return Err(templated_error(
+            "Invalid credentials",
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+    if granting_groups(&config, user, &executable).is_empty() {
+        return Err(templated_error(
+            "You don't have access to this executable",
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+
+    Ok(req)
+}
 
-    let user = match user_opt {
-        Some(user) => user,
+pub async fn request_validator(
+    req: ServiceRequest,
+    creds: Option<BasicAuth>,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let state = req.app_data::<web::Data<BarnState>>().unwrap().clone();
+    let config = state.config();
+    let (path, scope) = unscoped_path(req.path(), &config.options);
+    if let Some(scope) = scope {
+        req.extensions_mut().insert(scope);
+    }
+    let mut executable = config.route(&path).unwrap_or(&path);
+
+    // nothing gets run while barn is down for maintenance
+    if config.options.maintenance {
+        return Err((maintenance_error(), req));
+    }
+
+    // with options.discovery_command, only the names it lists can be run.
+    // the fallback is the operator's own choice, so it's exempt
+    let discovered = match state.discovery.names(&config.options).await {
+        Ok(discovered) => discovered,
+        Err(e) => {
+            return Err((
+                error_response(
+                    "Unable to discover the executables",
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Some(e),
+                ),
+                req,
+            ))
+        }
+    };
+    let undiscovered = |name: &str| {
+        config.options.fallback_executable.as_deref() != Some(name)
+            && discovered
+                .as_ref()
+                .is_some_and(|names| !names.iter().any(|entry| entry == name))
+    };
+
+    // paths that don't name an executable are handed to the fallback, if
+    // there is one, which is then checked like any other executable. that
+    // goes for discovered names with no file behind them too
+    if let Some(fallback) = &config.options.fallback_executable {
+        let unmatched = !FILENAME_REGEX.is_match(executable)
+            || undiscovered(executable)
+            || resolve_executable(executable, &config.options)
+                .is_none_or(|name| !config.options.root.join(name).is_file());
+        if unmatched {
+            req.extensions_mut().insert(RequestedPath(path.clone()));
+            executable = fallback;
+        }
+    }
+
+    if !FILENAME_REGEX.is_match(executable) {
+        return Err((
+            templated_error("Disallowed filename", StatusCode::BAD_REQUEST),
+            req,
+        ));
+    }
+
+    let executable = match resolve_executable(executable, &config.options) {
+        Some(executable) => executable,
         None => {
             return Err((
-                templated_error("Invalid credentials", StatusCode::BAD_REQUEST),
+                templated_error(
+                    "More than one executable matches this name",
+                    StatusCode::BAD_REQUEST,
+                ),
                 req,
             ))
         }
     };
+    let program_path = config.options.root.join(&executable);
+
+    // groups with a schedule only let their executables run during it
+    let now = match config.options.schedule_utc_offset {
+        Some(offset) => Utc::now().with_timezone(&offset).naive_local(),
+        None => Local::now().naive_local(),
+    };
+    let closed_schedule = config
+        .group
+        .iter()
+        .filter(|entry| entry.regex.is_match(&executable) && !entry.schedule.is_empty())
+        .find(|entry| !entry.schedule.iter().any(|window| window.allows(now)));
+
+    if let Some(entry) = closed_schedule {
+        let windows = entry
+            .schedule
+            .iter()
+            .map(|window| escape_html(window.text()))
+            .collect::<Vec<_>>();
+        return Err((
+            templated_error(
+                &format!(
+                    "This executable may only run during: {}",
+                    windows.join(", ")
+                ),
+                StatusCode::FORBIDDEN,
+            ),
+            req,
+        ));
+    }
+    req.extensions_mut()
+        .insert(ResolvedExecutable(executable.clone()));
+
+    if undiscovered(&executable) || !program_path.exists() || !program_path.is_file() {
+        let suggestion = config
+            .options
+            .suggest_executables
+            .then(|| {
+                suggest_executable(
+                    req.request(),
+                    creds.as_ref(),
+                    &config,
+                    discovered.as_deref(),
+                    &executable,
+                )
+            })
+            .flatten();
+        let message = match suggestion {
+            Some(name) => format!(
+                "Non-existent executable, did you mean <a href=\"/{0}\">{0}</a>?",
+                escape_html(&name)
+            ),
+            None => "Non-existent executable".to_string(),
+        };
+
+        return Err((templated_error(&message, StatusCode::NOT_FOUND), req));
+    }
+
+    // spawning a file without the execute bit would only fail with a generic error
+    if !is_executable(&program_path) {
+        return Err((
+            templated_error("File is not executable", StatusCode::FORBIDDEN),
+            req,
+        ));
+    }
+
+    // oversized files that turned up after startup are refused in strict mode
+    let oversized = config.options.strict_executable_size
+        && config.options.max_executable_size.is_some_and(|max_size| {
+            std::fs::metadata(&program_path).is_ok_and(|metadata| metadata.len() > max_size)
+        });
+    if oversized {
+        return Err((
+            templated_error(
+                "Executable is larger than the allowed size",
+                StatusCode::FORBIDDEN,
+            ),
+            req,
+        ));
+    }
 
-    // check if said user has access to the script group
-    let has_access = config
+    if untrusted_executable(&program_path, &config.options).is_some() {
+        return Err((
+            templated_error(
+                "Executable's ownership or permissions aren't trusted",
+                StatusCode::FORBIDDEN,
+            ),
+            req,
+        ));
+    }
+
+    // credentials and output shouldn't cross the network in plaintext
+    if config.options.require_https && !is_https(req.request(), &config.options.trusted_proxies) {
+        return Err((
+            templated_error(
+                "Executions are only allowed over HTTPS",
+                StatusCode::FORBIDDEN,
+            ),
+            req,
+        ));
+    }
+
+    // browsers can't add custom headers to plain links or form posts, so
+    // requiring one keeps other sites from triggering executions
+    let required_header = config
         .group
         .iter()
-        .filter(|entry| user.groups.contains(&entry.name))
-        .any(|entry| entry.regex.is_match(executable));
+        .filter(|entry| entry.regex.is_match(&executable))
+        .find_map(|entry| entry.require_header.as_ref())
+        .or(config.options.require_header.as_ref());
 
-    if has_access {
+    if let Some(header) = required_header {
+        if !req.headers().contains_key(header.as_str()) {
+            return Err((
+                templated_error(
+                    &format!("Missing required header '{}'", escape_html(header)),
+                    StatusCode::BAD_REQUEST,
+                ),
+                req,
+            ));
+        }
+    }
+
+    // auth has been explicitly disabled for every script
+    if config.options.auth == AuthMode::None {
+        return Ok(req);
+    }
+
+    // if this script belongs to the 'passwordless' group, no auth should be done
+    let is_passwordless = config
+        .group
+        .iter()
+        .any(|entry| entry.name == "passwordless" && entry.regex.is_match(&executable));
+
+    if is_passwordless {
+        req.extensions_mut()
+            .insert(AuthorizingGroups(vec!["passwordless".to_string()]));
+        return Ok(req);
+    }
+
+    // the executable or its groups may let specific hosts in without
+    // credentials
+    let ip = client_ip(req.request(), &config.options.trusted_proxies);
+    let trusts = |nets: &[IpNet]| ip.is_some_and(|ip| nets.iter().any(|net| net.contains(&ip)));
+    let trusting_groups = config
+        .group
+        .iter()
+        .filter(|entry| entry.regex.is_match(&executable))
+        .filter(|entry| trusts(&entry.trusted_ips))
+        .map(|entry| entry.name.clone())
+        .collect::<Vec<_>>();
+    let trusted_by_executable = config
+        .executable(&executable)
+        .is_some_and(|entry| trusts(&entry.trusted_ips));
+
+    if !trusting_groups.is_empty() {
+        req.extensions_mut()
+            .insert(AuthorizingGroups(trusting_groups));
+        return Ok(req);
+    }
+    if trusted_by_executable {
+        return Ok(req);
+    }
+
+    let user = match authenticate(req.request(), creds, &config) {
+        Ok(user) => user,
+        Err(e) => return Err((e, req)),
+    };
+
+    // check if said user has access to the script group, either directly
+    // or through the groups every user gets
+    let granting_groups = granting_groups(&config, user, &executable);
+    if !granting_groups.is_empty() {
+        if let Some(target) = config.rewrite(user, &executable) {
+            if !is_executable(&config.options.root.join(target)) {
+                return Err((
+                    templated_error(
+                        "The executable this request was rewritten to isn't runnable",
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                    req,
+                ));
+            }
+            req.extensions_mut()
+                .insert(ResolvedExecutable(target.to_string()));
+        }
+
+        let username = user.username.clone();
+        req.extensions_mut().insert(AuthenticatedUser(username));
+        req.extensions_mut()
+            .insert(AuthorizingGroups(granting_groups));
         Ok(req)
     } else {
-        return Err((
+        log_rejection(
+            req.request(),
+            &format!("user '{}' denied access to '{}'", user.username, executable),
+        );
+        Err((
             templated_error(
                 "You don't have access to this executable",
                 StatusCode::UNAUTHORIZED,
             ),
             req,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, TestRequest},
+    };
+
+    use super::*;
+    use crate::testing::{app, basic_auth, body_text, TestRoot, ERROR_PAGE_TESTS, USER};
+
+    fn forwarded_request(peer: &str, forwarded_for: &str) -> HttpRequest {
+        TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("X-Forwarded-For", forwarded_for))
+            .to_http_request()
+    }
+
+    #[test]
+    fn trusted_proxies_name_the_client() {
+        let proxies = ["10.0.0.0/8".parse().unwrap()];
+
+        // the nearest hop that isn't one of our proxies is the client
+        let req = forwarded_request("10.0.0.1:1234", "203.0.113.9, 198.51.100.7, 10.0.0.2");
+        assert_eq!(
+            client_ip(&req, &proxies),
+            Some("198.51.100.7".parse().unwrap())
+        );
+
+        // garbage in the header leaves the proxy itself
+        let req = forwarded_request("10.0.0.1:1234", "not an ip");
+        assert_eq!(client_ip(&req, &proxies), Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_forward() {
+        let proxies = ["10.0.0.0/8".parse().unwrap()];
+        let req = forwarded_request("192.0.2.1:1234", "203.0.113.9");
+        assert_eq!(
+            client_ip(&req, &proxies),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(client_ip(&req, &[]), Some("192.0.2.1".parse().unwrap()));
+    }
+
+    #[actix_web::test]
+    async fn trusted_proxies_may_vouch_for_users() {
+        let root = TestRoot::new();
+        root.script("whoami.sh", "echo ran");
+        let config = root.config(&format!(
+            "[options]\ntrusted_proxies = [\"10.0.0.0/8\"]\ntrusted_user_header = \"X-Remote-User\"\n{}",
+            USER
         ));
+        let app = app(config).await;
+        let request = |peer: &str, user: &str| {
+            TestRequest::get()
+                .uri("/whoami.sh?raw")
+                .peer_addr(peer.parse().unwrap())
+                .insert_header(("X-Remote-User", user))
+                .to_request()
+        };
+
+        let vouched = call_service(&app, request("10.0.0.1:1234", "user")).await;
+        assert_eq!(vouched.status(), StatusCode::OK);
+        assert_eq!(body_text(vouched).await, "ran\n");
+
+        let unknown = call_service(&app, request("10.0.0.1:1234", "nobody")).await;
+        assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+
+        // anyone else sending the header is asked for credentials
+        let untrusted = call_service(&app, request("192.0.2.1:1234", "user")).await;
+        assert_eq!(untrusted.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn disabled_auth_needs_no_credentials() {
+        let root = TestRoot::new();
+        root.script("open.sh", "echo open");
+        let app = app(root.config("[options]\nauth = \"none\"\n")).await;
+
+        let request = TestRequest::get().uri("/open.sh?raw").to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_text(response).await, "open\n");
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn files_without_the_execute_bit_are_refused() {
+        let root = TestRoot::new();
+        root.file("plain.sh", "#!/bin/sh\necho plain\n");
+        let app = app(root.config("[options]\nauth = \"none\"\n")).await;
+
+        let request = TestRequest::get().uri("/plain.sh").to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(body_text(response).await.contains("File is not executable"));
+    }
+
+    #[test]
+    fn server_errors_carry_their_log_reference() {
+        let (message, logged) =
+            referenced_error("Unable to spawn", Some("permission denied".to_string()));
+        let reference = message
+            .strip_prefix("Unable to spawn (reference: ")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .unwrap();
+        assert_eq!(reference.len(), 8);
+        assert_eq!(
+            logged,
+            format!("[{}] Unable to spawn: permission denied", reference)
+        );
+
+        let (other, _) = referenced_error("Unable to spawn", None);
+        assert_ne!(other, message);
+    }
+
+    #[actix_web::test]
+    async fn server_error_pages_show_a_reference() {
+        let error = templated_error("Something broke", StatusCode::INTERNAL_SERVER_ERROR);
+        let response = error.error_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Something broke (reference: "));
+
+        let error = templated_error("Bad request", StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(error.error_response().into_body())
+            .await
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("reference"));
+    }
+
+    #[actix_web::test]
+    async fn maintenance_refuses_executions_but_keeps_the_index() {
+        let root = TestRoot::new();
+        root.script("hello.sh", "echo hello");
+        let app = app(root.config(&format!("[options]\nmaintenance = true\n{}", USER))).await;
+        let request = |uri: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header(basic_auth("user", "password"))
+                .to_request()
+        };
+
+        let execution = call_service(&app, request("/hello.sh")).await;
+        assert_eq!(execution.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body_text(execution).await.contains("down for maintenance"));
+
+        let index = call_service(&app, request("/")).await;
+        assert_eq!(index.status(), StatusCode::OK);
+        assert!(body_text(index).await.contains("hello.sh"));
+    }
+
+    #[actix_web::test]
+    async fn executions_need_the_required_header() {
+        let root = TestRoot::new();
+        root.script("deploy.sh", "echo deployed");
+        let config = root.config(&format!(
+            "[options]\nrequire_header = \"X-Requested-By\"\n{}",
+            USER
+        ));
+        let app = app(config).await;
+
+        let request = TestRequest::get()
+            .uri("/deploy.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let blocked = call_service(&app, request).await;
+        assert_eq!(blocked.status(), StatusCode::BAD_REQUEST);
+        assert!(body_text(blocked)
+            .await
+            .contains("Missing required header 'X-Requested-By'"));
+
+        let request = TestRequest::get()
+            .uri("/deploy.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .insert_header(("X-Requested-By", "cli"))
+            .to_request();
+        assert_eq!(
+            body_text(call_service(&app, request).await).await,
+            "deployed\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn plaintext_is_refused_when_https_is_required() {
+        let root = TestRoot::new();
+        root.script("secret.sh", "echo secret");
+        let config = root.config(&format!(
+            "[options]\nrequire_https = true\ntrusted_proxies = [\"10.0.0.0/8\"]\n{}",
+            USER
+        ));
+        let app = app(config).await;
+        let request = |proto: &str| {
+            TestRequest::get()
+                .uri("/secret.sh?raw")
+                .peer_addr("10.0.0.1:1234".parse().unwrap())
+                .insert_header(basic_auth("user", "password"))
+                .insert_header(("X-Forwarded-Proto", proto))
+                .to_request()
+        };
+
+        let plaintext = call_service(&app, request("http")).await;
+        assert_eq!(plaintext.status(), StatusCode::FORBIDDEN);
+        assert!(body_text(plaintext)
+            .await
+            .contains("Executions are only allowed over HTTPS"));
+
+        let secure = call_service(&app, request("https")).await;
+        assert_eq!(body_text(secure).await, "secret\n");
+    }
+
+    #[actix_web::test]
+    async fn paths_are_forgiving_when_configured() {
+        let root = TestRoot::new();
+        root.script("Report.sh", "echo report");
+        root.script("build.sh", "echo build");
+        root.script("Build.sh", "echo Build");
+        let config = root.config(&format!(
+            "[options]\ncase_insensitive_paths = true\n{}",
+            USER
+        ));
+        let app = app(config).await;
+        let request = |uri: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header(basic_auth("user", "password"))
+                .to_request()
+        };
+
+        let trailing_slash = call_service(&app, request("/Report.sh/?raw")).await;
+        assert_eq!(body_text(trailing_slash).await, "report\n");
+
+        let other_case = call_service(&app, request("/report.SH?raw")).await;
+        assert_eq!(body_text(other_case).await, "report\n");
+
+        // an exact match always wins, but otherwise the name has to be unique
+        let exact = call_service(&app, request("/build.sh?raw")).await;
+        assert_eq!(body_text(exact).await, "build\n");
+        let ambiguous = call_service(&app, request("/BUILD.sh?raw")).await;
+        assert_eq!(ambiguous.status(), StatusCode::BAD_REQUEST);
+        assert!(body_text(ambiguous)
+            .await
+            .contains("More than one executable matches this name"));
+    }
+
+    #[actix_web::test]
+    async fn default_groups_apply_to_every_user() {
+        let root = TestRoot::new();
+        root.script("status.sh", "echo ok");
+        root.script("deploy.sh", "echo deployed");
+        let config = root.config(concat!(
+            "[options]\ndefault_groups = [\"viewers\"]\n",
+            "[[user]]\nusername = \"user\"\npassword = \"password\"\ngroups = []\n",
+            "[[group]]\nname = \"viewers\"\nregex = \"^status\"\n",
+            "[[group]]\nname = \"deployers\"\nregex = \"^deploy\"\n",
+        ));
+        let app = app(config).await;
+        let request = |uri: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header(basic_auth("user", "password"))
+                .to_request()
+        };
+
+        let allowed = call_service(&app, request("/status.sh?raw")).await;
+        assert_eq!(body_text(allowed).await, "ok\n");
+
+        let denied = call_service(&app, request("/deploy.sh?raw")).await;
+        assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn audit_lines_carry_the_query_but_never_credentials() {
+        let options = Options {
+            audit_include_query: true,
+            audit_headers: vec!["User-Agent".to_string(), "Authorization".to_string()],
+            ..Options::default()
+        };
+        let req = TestRequest::get()
+            .uri("/deploy.sh?env=prod&dry=1")
+            .insert_header(("User-Agent", "curl"))
+            .insert_header(basic_auth("user", "password"))
+            .to_http_request();
+
+        let line = audit_line(&req, "1234", "deploy.sh", Some("user"), &options);
+        assert!(
+            line.starts_with("1234 'user' ran 'deploy.sh' from "),
+            "{}",
+            line
+        );
+        assert!(line.contains(" query: env=prod&dry=1"), "{}", line);
+        assert!(line.contains(" headers: User-Agent=\"curl\""), "{}", line);
+        assert!(!line.contains("Authorization"), "{}", line);
+
+        let options = Options {
+            audit_include_query: false,
+            ..options
+        };
+        let line = audit_line(&req, "1234", "deploy.sh", Some("user"), &options);
+        assert!(!line.contains("query"), "{}", line);
+    }
+
+    #[actix_web::test]
+    async fn missing_executables_suggest_close_matches() {
+        let root = TestRoot::new();
+        root.script("backup.sh", "true");
+        let config = root.config(&format!("[options]\nsuggest_executables = true\n{}", USER));
+        let app = app(config).await;
+
+        let request = TestRequest::get()
+            .uri("/bakup.sh")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = body_text(response).await;
+        assert!(
+            body.contains("did you mean <a href=\"/backup.sh\">backup.sh</a>?"),
+            "{}",
+            body
+        );
+    }
+
+    #[actix_web::test]
+    async fn trusted_ips_run_without_credentials() {
+        let root = TestRoot::new();
+        root.script("health.sh", "echo healthy");
+        let config = root.config(&format!(
+            "{}\n[[group]]\nname = \"monitoring\"\nregex = \"^health\"\ntrusted_ips = [\"192.0.2.0/24\"]\n",
+            USER
+        ));
+        let app = app(config).await;
+        let request = |peer: &str| {
+            TestRequest::get()
+                .uri("/health.sh?raw")
+                .peer_addr(peer.parse().unwrap())
+                .to_request()
+        };
+
+        let trusted = call_service(&app, request("192.0.2.10:1234")).await;
+        assert_eq!(body_text(trusted).await, "healthy\n");
+
+        let untrusted = call_service(&app, request("198.51.100.7:1234")).await;
+        assert_eq!(untrusted.status(), StatusCode::UNAUTHORIZED);
+        assert!(untrusted.headers().contains_key("www-authenticate"));
+    }
+
+    #[actix_web::test]
+    async fn executables_can_trust_ips_of_their_own() {
+        let root = TestRoot::new();
+        root.script("health.sh", "echo healthy");
+        root.script("status.sh", "echo fine");
+        let config = root.config(&format!(
+            "{}\n[[executable]]\nname = \"health.sh\"\ntrusted_ips = [\"192.0.2.0/24\"]\n",
+            USER
+        ));
+        let app = app(config).await;
+        let request = |uri: &str, peer: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .peer_addr(peer.parse().unwrap())
+                .to_request()
+        };
+
+        let trusted = call_service(&app, request("/health.sh?raw", "192.0.2.10:1234")).await;
+        assert_eq!(body_text(trusted).await, "healthy\n");
+
+        let untrusted = call_service(&app, request("/health.sh?raw", "198.51.100.7:1234")).await;
+        assert_eq!(untrusted.status(), StatusCode::UNAUTHORIZED);
+
+        let other = call_service(&app, request("/status.sh?raw", "192.0.2.10:1234")).await;
+        assert_eq!(other.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn schedules_open_and_close_executables() {
+        let root = TestRoot::new();
+        root.script("open.sh", "echo open");
+        root.script("closed.sh", "echo closed");
+        let now = Local::now();
+        let window = |from: i64, to: i64| {
+            let time = |hours| (now + chrono::TimeDelta::hours(hours)).format("%H:%M");
+            format!("{}-{}", time(from), time(to))
+        };
+        let config = root.config(&format!(
+            "{}\n[[group]]\nname = \"business\"\nregex = \"^open\"\nschedule = [\"{}\"]\n\
+             [[group]]\nname = \"maintenance\"\nregex = \"^closed\"\nschedule = [\"{}\"]\n",
+            USER,
+            window(-1, 1),
+            window(2, 3)
+        ));
+        let app = app(config).await;
+        let request = |uri: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header(basic_auth("user", "password"))
+                .to_request()
+        };
+
+        let open = call_service(&app, request("/open.sh?raw")).await;
+        assert_eq!(body_text(open).await, "open\n");
+
+        let closed = call_service(&app, request("/closed.sh?raw")).await;
+        assert_eq!(closed.status(), StatusCode::FORBIDDEN);
+        let body = body_text(closed).await;
+        assert!(
+            body.contains(&format!(
+                "This executable may only run during: {}",
+                window(2, 3)
+            )),
+            "{}",
+            body
+        );
+    }
+
+    #[actix_web::test]
+    async fn routes_map_paths_to_executables() {
+        let root = TestRoot::new();
+        root.script("deploy_v2.sh", "echo \"deploying with $(basename \"$0\")\"");
+        let config = root.config(&format!(
+            "{}\n[[route]]\npath = \"/deploy\"\nexecutable = \"deploy_v2.sh\"\n",
+            USER
+        ));
+        let app = app(config).await;
+
+        let request = TestRequest::get()
+            .uri("/deploy?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(body_text(response).await, "deploying with deploy_v2.sh\n");
+    }
+
+    #[actix_web::test]
+    async fn oversized_executables_are_refused_in_strict_mode() {
+        let root = TestRoot::new();
+        let config = root.config(&format!(
+            "[options]\nmax_executable_size = 1024\nstrict_executable_size = true\n{}",
+            USER
+        ));
+        let app = app(config).await;
+        // turned up after startup, so the config check never saw it
+        root.script("big.sh", &format!("echo ran\n#{}", "x".repeat(2048)));
+
+        let request = TestRequest::get()
+            .uri("/big.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(body_text(response)
+            .await
+            .contains("Executable is larger than the allowed size"));
+    }
+
+    #[actix_web::test]
+    async fn unknown_paths_run_the_fallback() {
+        let root = TestRoot::new();
+        root.script("router.sh", "echo \"routing $1 ($BARN_REQUESTED_PATH)\"");
+        root.script("known.sh", "echo known");
+        let request = |uri: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header(basic_auth("user", "password"))
+                .to_request()
+        };
+
+        let routing = app(root.config(&format!(
+            "[options]\nfallback_executable = \"router.sh\"\n{}",
+            USER
+        )))
+        .await;
+        let routed = call_service(&routing, request("/reports/weekly?raw")).await;
+        assert_eq!(
+            body_text(routed).await,
+            "routing reports/weekly (reports/weekly)\n"
+        );
+        let known = call_service(&routing, request("/known.sh?raw")).await;
+        assert_eq!(body_text(known).await, "known\n");
+
+        // without a fallback, unknown paths stay errors
+        let plain = app(root.config(USER)).await;
+        let missing = call_service(&plain, request("/reports/weekly?raw")).await;
+        assert!(missing.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn beta_users_are_rewritten_to_the_new_version() {
+        let root = TestRoot::new();
+        root.script("feature.sh", "echo stable");
+        root.script("feature_v2.sh", "echo beta");
+        let config = root.config(concat!(
+            "[[user]]\nusername = \"user\"\npassword = \"password\"\ngroups = [\"everyone\"]\n",
+            "[[user]]\nusername = \"tester\"\npassword = \"password\"\n",
+            "groups = [\"everyone\", \"beta\"]\n",
+            "[[group]]\nname = \"everyone\"\nregex = \".*\"\n",
+            "[[group]]\nname = \"beta\"\nregex = \"^$\"\n",
+            "[[rewrite]]\nexecutable = \"feature.sh\"\nto = \"feature_v2.sh\"\ngroup = \"beta\"\n",
+        ));
+        let app = app(config).await;
+        let request = |username: &str| {
+            TestRequest::get()
+                .uri("/feature.sh?raw")
+                .insert_header(basic_auth(username, "password"))
+                .to_request()
+        };
+
+        let regular = call_service(&app, request("user")).await;
+        assert_eq!(body_text(regular).await, "stable\n");
+
+        let beta = call_service(&app, request("tester")).await;
+        assert_eq!(body_text(beta).await, "beta\n");
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn writable_executables_are_refused() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let root = TestRoot::new();
+        let safe = root.script("safe.sh", "echo safe");
+        let writable = root.script("writable.sh", "echo tampered");
+        std::fs::set_permissions(&writable, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let owner = std::fs::metadata(&safe).unwrap().uid();
+        let request = |uri: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header(basic_auth("user", "password"))
+                .to_request()
+        };
+
+        let checked = app(root.config(&format!(
+            "[options]\nrequire_owner = {}\nreject_writable = true\n{}",
+            owner, USER
+        )))
+        .await;
+        let allowed = call_service(&checked, request("/safe.sh?raw")).await;
+        assert_eq!(body_text(allowed).await, "safe\n");
+        let refused = call_service(&checked, request("/writable.sh?raw")).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        assert!(body_text(refused)
+            .await
+            .contains("Executable's ownership or permissions aren't trusted"));
+
+        let other_owner = app(root.config(&format!(
+            "[options]\nrequire_owner = {}\n{}",
+            owner + 1,
+            USER
+        )))
+        .await;
+        let refused = call_service(&other_owner, request("/safe.sh?raw")).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn error_pages_are_picked_by_status() {
+        let _error_pages = ERROR_PAGE_TESTS.lock().await;
+        let root = TestRoot::new();
+        root.file("404.html", "<h1>Nothing here</h1><p>{message}</p>\n");
+        let config = root.config(&format!(
+            "[options.error_pages]\n404 = {{ file = {:?} }}\n403 = {{ file = {:?} }}\n{}",
+            root.path().join("404.html"),
+            root.path().join("missing.html"),
+            USER
+        ));
+        let error_pages = config.options.error_pages.clone();
+        set_error_pages(&error_pages);
+        let app = app(config).await;
+
+        let request = TestRequest::get()
+            .uri("/missing.sh")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            body_text(response).await,
+            "<h1>Nothing here</h1><p>Non-existent executable</p>\n"
+        );
+
+        // unreadable and unmapped pages fall back to the generic one
+        for status in [StatusCode::FORBIDDEN, StatusCode::BAD_REQUEST] {
+            assert_eq!(
+                page_for_status(&error_pages, "Nope", status),
+                templated_page("Nope")
+            );
+        }
+        set_error_pages(&HashMap::new());
     }
 }
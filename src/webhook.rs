@@ -0,0 +1,121 @@
+use colored::Colorize;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::time::sleep;
+
+// how long a single delivery attempt may take
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Webhook {
+    pub url: String,
+    pub retries: u32,
+}
+
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub execution_id: String,
+    pub executable: String,
+    pub user: Option<String>,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u128,
+    pub output_tail: Vec<String>,
+    pub output_bytes: u64,
+    pub rendered_bytes: u64,
+}
+
+// delivers the summary in the background, backing off exponentially
+// between attempts. the client has already been served, so a webhook
+// that stays down is only logged
+pub fn send(webhook: Webhook, summary: RunSummary) {
+    actix_web::rt::spawn(async move {
+        let client = awc::Client::default();
+        let mut backoff = Duration::from_secs(1);
+        let mut last_error = String::new();
+
+        for attempt in 0..=webhook.retries {
+            let response = client
+                .post(&webhook.url)
+                .timeout(WEBHOOK_TIMEOUT)
+                .send_json(&summary)
+                .await;
+
+            match response {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => last_error = format!("responded with {}", response.status()),
+                Err(e) => last_error = e.to_string(),
+            }
+
+            if attempt < webhook.retries {
+                sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+
+        println!(
+            "{} webhook for '{}' ({}) failed after {} attempts: {}",
+            "[warn]".bold().yellow(),
+            summary.executable,
+            summary.execution_id,
+            webhook.retries + 1,
+            last_error
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, time::Duration};
+
+    use actix_web::{test, web, App, HttpResponse, HttpServer};
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+
+    use crate::testing::{app, basic_auth, body_text, TestRoot, USER};
+
+    #[actix_web::test]
+    async fn summaries_reach_the_webhook() {
+        let (sender, mut summaries) = mpsc::unbounded_channel::<Value>();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let receiver = HttpServer::new(move || {
+            let sender = sender.clone();
+            App::new().route(
+                "/hook",
+                web::post().to(move |summary: web::Json<Value>| {
+                    let _ = sender.send(summary.into_inner());
+                    async { HttpResponse::Ok().finish() }
+                }),
+            )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        let handle = receiver.handle();
+        actix_web::rt::spawn(receiver);
+
+        let root = TestRoot::new();
+        root.script("report.sh", "echo one; echo two; exit 4");
+        let config = root.config(&format!(
+            "[options]\nwebhook = \"http://{}/hook\"\n{}",
+            address, USER
+        ));
+        let app = app(config).await;
+        let request = test::TestRequest::get()
+            .uri("/report.sh?raw")
+            .insert_header(basic_auth("user", "password"))
+            .to_request();
+        body_text(test::call_service(&app, request).await).await;
+
+        let summary = tokio::time::timeout(Duration::from_secs(5), summaries.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary["executable"], "report.sh");
+        assert_eq!(summary["user"], "user");
+        assert_eq!(summary["exit_code"], 4);
+        assert_eq!(summary["output_tail"], serde_json::json!(["one", "two"]));
+        assert_eq!(summary["output_bytes"], 8);
+        handle.stop(false).await;
+    }
+}